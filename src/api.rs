@@ -0,0 +1,827 @@
+//! HTTP API over the storage, so consumers don't have to know which database
+//! the sentry writes to or how it lays out its data.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+use tracing::{error, info};
+
+use crate::fingerprint::Software;
+use crate::login::probe_login;
+use crate::metrics;
+use crate::model::failure::ProbeFailure;
+use crate::model::observation::ServerObservation;
+use crate::model::server::ServerStatus;
+use crate::model::session::PlayerSession;
+use crate::probe::probe_address;
+use crate::protocol::{self, Edition};
+use crate::ratelimit::{RateLimiter, RateLimits};
+use crate::storage::{
+    HistoryQuery, PendingWrite, PlayerCount, PlayerFilter, PlayerRow, ServerFilter, ServerRow,
+    Storage, StorageError, Uptime,
+};
+use crate::UPTIME_WINDOWS;
+
+/// Servers listed when the request doesn't say how many.
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Most recent sessions returned with a player.
+const MAX_SESSIONS: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct ApiOptions {
+    /// Per phase timeout of on-demand probes
+    pub timeout: Duration,
+    /// Keep the raw status JSON of on-demand probes
+    pub store_raw: bool,
+    /// Also attempt a login with servers probed on demand
+    pub login_probe: bool,
+    /// Most on-demand probes running at once
+    pub probe_concurrency: usize,
+    /// Probe non-public addresses on demand too
+    pub allow_private_probes: bool,
+    pub rate_limits: RateLimits,
+    /// How often to refresh the online server and player gauges
+    pub metrics_refresh: Duration,
+}
+
+#[derive(Clone)]
+struct ApiState {
+    storage: Arc<dyn Storage>,
+    timeout: Duration,
+    store_raw: bool,
+    login_probe: bool,
+    /// On-demand probes that may run now
+    probes: Arc<Semaphore>,
+    limiter: Arc<RateLimiter>,
+    allow_private_probes: bool,
+}
+
+/// Routes of the API, `/metrics` included.
+pub fn router(storage: Arc<dyn Storage>, options: &ApiOptions) -> Router {
+    Router::new()
+        .route("/servers", get(list_servers))
+        .route("/servers/{host}/{port}", get(server_details))
+        .route("/servers/{host}/{port}/history", get(server_history))
+        .route("/players/{player}", get(find_player))
+        .route("/protocols/{edition}/{protocol}", get(protocol_releases))
+        .route("/probe", post(probe_now))
+        .with_state(ApiState {
+            storage,
+            timeout: options.timeout,
+            store_raw: options.store_raw,
+            login_probe: options.login_probe,
+            probes: Arc::new(Semaphore::new(options.probe_concurrency)),
+            limiter: Arc::new(RateLimiter::new(options.rate_limits)),
+            allow_private_probes: options.allow_private_probes,
+        })
+        .merge(metrics::router())
+}
+
+/// Serves the API on `addr` until Ctrl-C or SIGTERM.
+pub async fn serve(addr: SocketAddr, storage: Arc<dyn Storage>, options: ApiOptions) {
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(error = %err, %addr, "error binding api");
+            return;
+        }
+    };
+    info!(%addr, "serving api");
+
+    let app = router(storage.clone(), &options);
+    let server =
+        axum::serve(listener, app).with_graceful_shutdown(crate::shutdown::shutdown_signal());
+
+    tokio::select! {
+        _ = metrics::refresh_ecosystem(storage, options.metrics_refresh) => {}
+        result = server => {
+            if let Err(err) = result {
+                error!(error = %err, "error serving api");
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ApiError {
+    BadRequest(String),
+    Forbidden(String),
+    NotFound(String),
+    /// Too many requests of this kind already being handled
+    Busy(String),
+    Storage(StorageError),
+}
+
+// Requests axum couldn't parse get the same error body as the rest
+impl From<JsonRejection> for ApiError {
+    fn from(value: JsonRejection) -> Self {
+        ApiError::BadRequest(value.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(value: PathRejection) -> Self {
+        ApiError::BadRequest(value.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(value: QueryRejection) -> Self {
+        ApiError::BadRequest(value.body_text())
+    }
+}
+
+impl From<StorageError> for ApiError {
+    fn from(value: StorageError) -> Self {
+        ApiError::Storage(value)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::Forbidden(message) => (StatusCode::FORBIDDEN, message),
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            ApiError::Busy(message) => (StatusCode::TOO_MANY_REQUESTS, message),
+            ApiError::Storage(err) => {
+                error!(error = %err, "error querying storage");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "storage error".to_owned(),
+                )
+            }
+        };
+        (status, Json(ErrorJson { error: message })).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Serialize)]
+struct ErrorJson {
+    error: String,
+}
+
+fn time(date: DateTime) -> String {
+    date.try_to_rfc3339_string().unwrap_or_default()
+}
+
+fn parse_time(name: &str, value: Option<&str>) -> Result<Option<DateTime>, ApiError> {
+    value
+        .map(|value| {
+            DateTime::parse_rfc3339_str(value)
+                .map_err(|err| ApiError::BadRequest(format!("{}: {}", name, err)))
+        })
+        .transpose()
+}
+
+#[derive(Serialize)]
+struct ModJson {
+    mod_id: String,
+    version: String,
+}
+
+#[derive(Serialize)]
+struct ServerJson {
+    host: String,
+    port: i16,
+    status: ServerStatus,
+    motd: String,
+    version_name: String,
+    protocol: i32,
+    online_players: i32,
+    max_players: i32,
+    software: String,
+    forge: bool,
+    mods: Vec<ModJson>,
+    last_updated: String,
+}
+
+impl From<ServerRow> for ServerJson {
+    fn from(row: ServerRow) -> Self {
+        let mods = row
+            .mods
+            .split(';')
+            .filter(|m| !m.is_empty())
+            .map(|m| {
+                let (mod_id, version) = m.split_once('@').unwrap_or((m, ""));
+                ModJson {
+                    mod_id: mod_id.to_owned(),
+                    version: version.to_owned(),
+                }
+            })
+            .collect();
+
+        Self {
+            host: row.host,
+            port: row.port,
+            status: row.status,
+            motd: row.motd,
+            version_name: row.version_name,
+            protocol: row.protocol,
+            online_players: row.online_players,
+            max_players: row.max_players,
+            software: row.software,
+            forge: row.forge,
+            mods,
+            last_updated: time(row.last_updated),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ServerQuery {
+    host: Option<String>,
+    protocol: Option<i32>,
+    software: Option<String>,
+    #[serde(rename = "mod")]
+    mod_id: Option<String>,
+    motd: Option<String>,
+    min_players: Option<i32>,
+    max_players: Option<i32>,
+    status: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// `GET /servers`, busiest first.
+async fn list_servers(
+    State(state): State<ApiState>,
+    query: Result<Query<ServerQuery>, QueryRejection>,
+) -> ApiResult<Vec<ServerJson>> {
+    let Query(query) = query?;
+    let status = query
+        .status
+        .as_deref()
+        .map(|status| {
+            ServerStatus::parse(status)
+                .ok_or_else(|| ApiError::BadRequest(format!("unknown status {}", status)))
+        })
+        .transpose()?;
+
+    let filter = ServerFilter {
+        host: query.host,
+        protocol: query.protocol,
+        min_players: query.min_players,
+        max_players: query.max_players,
+        status,
+        software: query.software,
+        mod_id: query.mod_id,
+        motd: query.motd,
+        limit: Some(query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)),
+        offset: Some(query.offset.unwrap_or(0).max(0)),
+        ..Default::default()
+    };
+    let rows = state.storage.export_servers(&filter).await?;
+
+    Ok(Json(rows.into_iter().map(ServerJson::from).collect()))
+}
+
+#[derive(Serialize)]
+struct UptimeJson {
+    probes: i64,
+    online: i64,
+    percent: Option<f64>,
+}
+
+impl From<Uptime> for UptimeJson {
+    fn from(uptime: Uptime) -> Self {
+        Self {
+            percent: uptime.percent(),
+            probes: uptime.probes,
+            online: uptime.online,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ServerDetailsJson {
+    #[serde(flatten)]
+    server: ServerJson,
+    status_since: Option<String>,
+    consecutive_failures: i32,
+    last_error: Option<String>,
+    last_error_at: Option<String>,
+    uptime: BTreeMap<&'static str, UptimeJson>,
+}
+
+async fn find_server(storage: &dyn Storage, host: &str, port: i16) -> Result<ServerRow, ApiError> {
+    let filter = ServerFilter {
+        host: Some(host.to_owned()),
+        port: Some(port),
+        limit: Some(1),
+        ..Default::default()
+    };
+    storage
+        .export_servers(&filter)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| ApiError::NotFound(format!("no server {}:{}", host, port)))
+}
+
+/// `GET /servers/{host}/{port}`, with status and uptime.
+async fn server_details(
+    State(state): State<ApiState>,
+    target: Result<Path<(String, i16)>, PathRejection>,
+) -> ApiResult<ServerDetailsJson> {
+    let Path((host, port)) = target?;
+    let storage = state.storage.as_ref();
+    let row = find_server(storage, &host, port).await?;
+    let server_state = storage.server_state(&host, port).await?;
+
+    let now = DateTime::now().timestamp_millis();
+    let mut uptime = BTreeMap::new();
+    for (name, window) in UPTIME_WINDOWS {
+        let query = HistoryQuery {
+            host: host.clone(),
+            port,
+            since: Some(DateTime::from_millis(now - window.as_millis() as i64)),
+            until: None,
+        };
+        uptime.insert(name, storage.uptime(&query).await?.into());
+    }
+
+    Ok(Json(ServerDetailsJson {
+        server: row.into(),
+        status_since: server_state
+            .as_ref()
+            .and_then(|state| state.status_since)
+            .map(time),
+        consecutive_failures: server_state
+            .as_ref()
+            .map(|state| state.consecutive_failures)
+            .unwrap_or_default(),
+        last_error: server_state
+            .as_ref()
+            .and_then(|state| state.last_error.clone()),
+        last_error_at: server_state
+            .as_ref()
+            .and_then(|state| state.last_error_at)
+            .map(time),
+        uptime,
+    }))
+}
+
+#[derive(Deserialize)]
+struct HistoryParams {
+    since: Option<String>,
+    until: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PlayerCountJson {
+    observed_at: String,
+    online_players: i32,
+    max_players: i32,
+}
+
+impl From<PlayerCount> for PlayerCountJson {
+    fn from(count: PlayerCount) -> Self {
+        Self {
+            observed_at: time(count.observed_at),
+            online_players: count.online_players,
+            max_players: count.max_players,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HistoryJson {
+    uptime: UptimeJson,
+    player_counts: Vec<PlayerCountJson>,
+}
+
+/// `GET /servers/{host}/{port}/history`, optionally between `since` and
+/// `until`.
+async fn server_history(
+    State(state): State<ApiState>,
+    target: Result<Path<(String, i16)>, PathRejection>,
+    params: Result<Query<HistoryParams>, QueryRejection>,
+) -> ApiResult<HistoryJson> {
+    let (Path((host, port)), Query(params)) = (target?, params?);
+    let storage = state.storage.as_ref();
+    find_server(storage, &host, port).await?;
+
+    let query = HistoryQuery {
+        host,
+        port,
+        since: parse_time("since", params.since.as_deref())?,
+        until: parse_time("until", params.until.as_deref())?,
+    };
+    let uptime = storage.uptime(&query).await?;
+    let counts = storage.player_counts(&query).await?;
+
+    Ok(Json(HistoryJson {
+        uptime: uptime.into(),
+        player_counts: counts.into_iter().map(PlayerCountJson::from).collect(),
+    }))
+}
+
+#[derive(Serialize)]
+struct SeenOnJson {
+    host: String,
+    port: i16,
+    first_seen: String,
+    last_seen: String,
+}
+
+#[derive(Serialize)]
+struct SessionJson {
+    host: String,
+    port: i16,
+    started_at: String,
+    last_seen: String,
+    ended_at: Option<String>,
+}
+
+impl From<&PlayerSession> for SessionJson {
+    fn from(session: &PlayerSession) -> Self {
+        Self {
+            host: session.host.clone(),
+            port: session.port,
+            started_at: time(session.started_at),
+            last_seen: time(session.last_seen),
+            ended_at: session.ended_at.map(time),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PlayerJson {
+    uuid: String,
+    name: String,
+    last_seen: String,
+    last_updated: String,
+    /// Every server the player was seen on, most recent first
+    servers: Vec<SeenOnJson>,
+    /// The most recent sessions, up to `MAX_SESSIONS`
+    sessions: Vec<SessionJson>,
+}
+
+/// Dashed lowercase form of a UUID written with or without dashes, `None`
+/// if `value` isn't one.
+fn normalize_uuid(value: &str) -> Option<String> {
+    let hex = value.replace('-', "").to_ascii_lowercase();
+    if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+/// `GET /players/{player}` by UUID or name. A name can match more than one
+/// player, names change hands.
+async fn find_player(
+    State(state): State<ApiState>,
+    Path(player): Path<String>,
+) -> ApiResult<Vec<PlayerJson>> {
+    let storage = state.storage.as_ref();
+    let filter = match normalize_uuid(&player) {
+        Some(uuid) => PlayerFilter {
+            uuid: Some(uuid),
+            ..Default::default()
+        },
+        None => PlayerFilter {
+            name: Some(player.clone()),
+            ..Default::default()
+        },
+    };
+
+    let rows = storage.export_players(&filter).await?;
+    if rows.is_empty() {
+        return Err(ApiError::NotFound(format!("no player {}", player)));
+    }
+
+    let mut players = Vec::with_capacity(rows.len());
+    for row in rows {
+        let sessions = storage.player_sessions(&row.uuid).await?;
+        players.push(player_json(row, &sessions));
+    }
+
+    Ok(Json(players))
+}
+
+fn player_json(row: PlayerRow, sessions: &[PlayerSession]) -> PlayerJson {
+    let mut seen_on = HashMap::<(&str, i16), (DateTime, DateTime)>::new();
+    for session in sessions {
+        let seen = seen_on
+            .entry((&session.host, session.port))
+            .or_insert((session.started_at, session.last_seen));
+        seen.0 = seen.0.min(session.started_at);
+        seen.1 = seen.1.max(session.last_seen);
+    }
+    let mut servers = seen_on.into_iter().collect::<Vec<_>>();
+    servers.sort_by_key(|(_, (_, last_seen))| Reverse(*last_seen));
+
+    PlayerJson {
+        uuid: row.uuid,
+        name: row.name,
+        last_seen: time(row.last_seen),
+        last_updated: time(row.last_updated),
+        servers: servers
+            .into_iter()
+            .map(|((host, port), (first_seen, last_seen))| SeenOnJson {
+                host: host.to_owned(),
+                port,
+                first_seen: time(first_seen),
+                last_seen: time(last_seen),
+            })
+            .collect(),
+        sessions: sessions
+            .iter()
+            .take(MAX_SESSIONS)
+            .map(SessionJson::from)
+            .collect(),
+    }
+}
+
+#[derive(Serialize)]
+struct ProtocolJson {
+    edition: Edition,
+    protocol: i32,
+    releases: &'static [&'static str],
+}
+
+/// Releases that speak a protocol number, for clients that only have the
+/// number, e.g. from a Bedrock server's pong.
+async fn protocol_releases(
+    target: Result<Path<(Edition, i32)>, PathRejection>,
+) -> ApiResult<ProtocolJson> {
+    let Path((edition, protocol)) = target?;
+    let releases = protocol::releases(edition, protocol);
+    if releases.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "no {:?} release uses protocol {}",
+            edition, protocol
+        )));
+    }
+
+    Ok(Json(ProtocolJson {
+        edition,
+        protocol,
+        releases,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ProbeRequest {
+    host: String,
+    port: i16,
+}
+
+#[derive(Serialize)]
+struct ProbeJson {
+    host: String,
+    port: i16,
+    online: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    protocol: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    online_players: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_players: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    motd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    software: Option<Software>,
+}
+
+/// `POST /probe` with `{"host": ..., "port": ...}`. Probes the host now and
+/// stores the result like a scan would. Probes share the scan rate limits,
+/// counted against the address the host resolves to, and at most
+/// `probe_concurrency` run at once, requests past that are turned away
+/// rather than queued.
+async fn probe_now(
+    State(state): State<ApiState>,
+    request: Result<Json<ProbeRequest>, JsonRejection>,
+) -> ApiResult<ProbeJson> {
+    let Json(request) = request?;
+    let address = resolve(&request.host, request.port, state.allow_private_probes).await?;
+    let _permit = state
+        .probes
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| ApiError::Busy("too many probes running".to_owned()))?;
+    state.limiter.acquire(&address.ip().to_string()).await;
+
+    let started_at = DateTime::now();
+    let start = Instant::now();
+    let probed = probe_address(address, &request.host, request.port, state.timeout).await;
+    let (write, json) = match probed {
+        Ok(mut response) => {
+            let latency = start.elapsed();
+            if state.login_probe {
+                probe_login(&mut response, state.timeout).await;
+            }
+            let observation = ServerObservation::from_response(&response, latency, state.store_raw);
+            let json = ProbeJson {
+                host: request.host,
+                port: request.port,
+                online: true,
+                error: None,
+                message: None,
+                latency_ms: Some(observation.latency_ms),
+                version_name: Some(observation.version.name.clone()),
+                protocol: Some(observation.version.protocol),
+                online_players: Some(observation.online.players),
+                max_players: Some(observation.online.max),
+                motd: Some(observation.motd.clone()),
+                software: Some(observation.software.software),
+            };
+            (PendingWrite::Observation(Box::new(observation)), json)
+        }
+        Err(err) => {
+            let failure = ProbeFailure::new(&request.host, request.port, started_at, &err);
+            let json = ProbeJson {
+                host: request.host,
+                port: request.port,
+                online: false,
+                error: Some(err.kind.as_str()),
+                message: Some(err.message),
+                latency_ms: None,
+                version_name: None,
+                protocol: None,
+                online_players: None,
+                max_players: None,
+                motd: None,
+                software: None,
+            };
+            (PendingWrite::Failure(failure), json)
+        }
+    };
+
+    state.storage.record_batch(&[write]).await?;
+    Ok(Json(json))
+}
+
+/// Looks the host up once and returns the address to probe, so a second
+/// lookup can't send the probe somewhere other than what was checked. Unless
+/// `allow_private`, refuses hosts that are, or resolve to, addresses outside
+/// the public internet, so the API can't be used to reach into the network
+/// it runs in.
+async fn resolve(host: &str, port: i16, allow_private: bool) -> Result<SocketAddr, ApiError> {
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port as u16))
+        .await
+        .map_err(|err| ApiError::BadRequest(format!("can't resolve {}: {}", host, err)))?
+        .collect();
+
+    if !allow_private {
+        if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+            return Err(ApiError::Forbidden(format!(
+                "{} is not a public address",
+                address.ip()
+            )));
+        }
+    }
+    addresses
+        .first()
+        .copied()
+        .ok_or_else(|| ApiError::BadRequest(format!("{} has no addresses", host)))
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local, fc00::/7
+                    || first & 0xfe00 == 0xfc00
+                    // Link local, fe80::/10
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::null::NullStorage;
+
+    use super::*;
+
+    fn state(probe_concurrency: usize, allow_private_probes: bool) -> ApiState {
+        ApiState {
+            storage: Arc::new(NullStorage),
+            timeout: Duration::from_millis(500),
+            store_raw: false,
+            login_probe: false,
+            probes: Arc::new(Semaphore::new(probe_concurrency)),
+            limiter: Arc::new(RateLimiter::new(RateLimits {
+                global: None,
+                per_subnet: None,
+                subnet_prefix: 24,
+            })),
+            allow_private_probes,
+        }
+    }
+
+    async fn probe_status(state: ApiState, host: &str) -> StatusCode {
+        let request = ProbeRequest {
+            host: host.to_owned(),
+            port: 25565,
+        };
+        match probe_now(State(state), Ok(Json(request))).await {
+            Ok(response) => response.into_response().status(),
+            Err(err) => err.into_response().status(),
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_probes_of_private_addresses() {
+        for host in ["127.0.0.1", "10.0.0.1", "localhost", "::1"] {
+            let status = probe_status(state(1, false), host).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", host);
+        }
+    }
+
+    #[tokio::test]
+    async fn turns_probes_away_once_all_are_running() {
+        let state = state(1, true);
+        let _running = state.probes.clone().try_acquire_owned().unwrap();
+
+        let status = probe_status(state, "127.0.0.1").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn resolves_the_address_to_probe() {
+        let address = resolve("localhost", 25565, true).await.unwrap();
+        assert!(address.ip().is_loopback());
+        assert_eq!(address.port(), 25565);
+    }
+
+    #[test]
+    fn tells_public_addresses_apart() {
+        for public in [
+            "1.1.1.1",
+            "100.128.0.1",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(is_public(public.parse().unwrap()), "{}", public);
+        }
+        for private in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(private.parse().unwrap()), "{}", private);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Progress of a scan saved to disk, so an interrupted scan can pick up
+//! where it stopped instead of probing everything again.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Which lines of the input have been probed. Probes finish out of order, so
+/// this is everything before `offset` plus whatever finished past it.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Input file the line numbers refer to
+    pub input: String,
+    /// Every line before this one has been probed
+    pub offset: usize,
+    /// Lines at or after `offset` that have been probed too
+    pub completed: BTreeSet<usize>,
+}
+
+impl Checkpoint {
+    pub fn new(input: &str) -> Self {
+        Self {
+            input: input.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// `None` if there is no checkpoint at `path`.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes to a temporary file first and renames it over `path`, so a
+    /// crash mid write leaves the previous checkpoint intact.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn is_done(&self, line: usize) -> bool {
+        line < self.offset || self.completed.contains(&line)
+    }
+
+    pub fn complete(&mut self, line: usize) {
+        if line < self.offset {
+            return;
+        }
+        self.completed.insert(line);
+        while self.completed.remove(&self.offset) {
+            self.offset += 1;
+        }
+    }
+}
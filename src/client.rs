@@ -9,16 +9,16 @@ pub struct MinecraftClient {
 }
 
 impl MinecraftClient {
-    pub async fn connect(mut self, address: SocketAddr) -> std::io::Result<()> {
+    pub async fn connect(self, address: SocketAddr) -> std::io::Result<()> {
         if let Some(mut old_stream) = self.stream {
             old_stream.shutdown().await?;
         }
 
         // Create stream
-        let stream = TcpStream::connect(address).await?;
+        let _stream = TcpStream::connect(address).await?;
 
         // Initiate connection
-        let handshake_packet =
+        let _handshake_packet =
             handshake_status_packet(&address.ip().to_string(), address.port() as i16);
         //stream.write
 
@@ -26,4 +26,4 @@ impl MinecraftClient {
     }
 }
 
-pub async fn connect(server: &MinecraftServer) {}
+pub async fn connect(_server: &MinecraftServer) {}
@@ -0,0 +1,173 @@
+//! Dumps servers and players to files analysts can load into pandas or DuckDB.
+
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Int16Array, Int32Array, RecordBatch, StringArray,
+    TimestampMillisecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use clap::ValueEnum;
+use mongodb::bson::DateTime;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::storage::{PlayerRow, ServerRow};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+fn rfc3339(date_time: DateTime) -> String {
+    date_time.try_to_rfc3339_string().unwrap_or_default()
+}
+
+pub fn write_servers(rows: &[ServerRow], path: &str, format: ExportFormat) -> io::Result<()> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_path(path)?;
+            writer.write_record([
+                "host",
+                "port",
+                "motd",
+                "version_name",
+                "protocol",
+                "online_players",
+                "max_players",
+                "software",
+                "forge",
+                "mods",
+                "last_updated",
+                "status",
+            ])?;
+            for row in rows {
+                writer.write_record([
+                    row.host.clone(),
+                    row.port.to_string(),
+                    row.motd.clone(),
+                    row.version_name.clone(),
+                    row.protocol.to_string(),
+                    row.online_players.to_string(),
+                    row.max_players.to_string(),
+                    row.software.clone(),
+                    row.forge.to_string(),
+                    row.mods.clone(),
+                    rfc3339(row.last_updated),
+                    row.status.as_str().to_owned(),
+                ])?;
+            }
+            writer.flush()
+        }
+        ExportFormat::Parquet => {
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.host))),
+                Arc::new(Int16Array::from_iter_values(rows.iter().map(|r| r.port))),
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.motd))),
+                Arc::new(StringArray::from_iter_values(
+                    rows.iter().map(|r| &r.version_name),
+                )),
+                Arc::new(Int32Array::from_iter_values(
+                    rows.iter().map(|r| r.protocol),
+                )),
+                Arc::new(Int32Array::from_iter_values(
+                    rows.iter().map(|r| r.online_players),
+                )),
+                Arc::new(Int32Array::from_iter_values(
+                    rows.iter().map(|r| r.max_players),
+                )),
+                Arc::new(StringArray::from_iter_values(
+                    rows.iter().map(|r| &r.software),
+                )),
+                Arc::new(BooleanArray::from_iter(rows.iter().map(|r| Some(r.forge)))),
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.mods))),
+                timestamps(rows.iter().map(|r| r.last_updated)),
+                Arc::new(StringArray::from_iter_values(
+                    rows.iter().map(|r| r.status.as_str()),
+                )),
+            ];
+            let schema = Schema::new(vec![
+                Field::new("host", DataType::Utf8, false),
+                Field::new("port", DataType::Int16, false),
+                Field::new("motd", DataType::Utf8, false),
+                Field::new("version_name", DataType::Utf8, false),
+                Field::new("protocol", DataType::Int32, false),
+                Field::new("online_players", DataType::Int32, false),
+                Field::new("max_players", DataType::Int32, false),
+                Field::new("software", DataType::Utf8, false),
+                Field::new("forge", DataType::Boolean, false),
+                Field::new("mods", DataType::Utf8, false),
+                timestamp_field("last_updated"),
+                Field::new("status", DataType::Utf8, false),
+            ]);
+            write_parquet(path, schema, columns)
+        }
+    }
+}
+
+pub fn write_players(rows: &[PlayerRow], path: &str, format: ExportFormat) -> io::Result<()> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_path(path)?;
+            writer.write_record(["uuid", "name", "last_seen", "last_updated"])?;
+            for row in rows {
+                writer.write_record([
+                    row.uuid.clone(),
+                    row.name.clone(),
+                    rfc3339(row.last_seen),
+                    rfc3339(row.last_updated),
+                ])?;
+            }
+            writer.flush()
+        }
+        ExportFormat::Parquet => {
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.uuid))),
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.name))),
+                timestamps(rows.iter().map(|r| r.last_seen)),
+                timestamps(rows.iter().map(|r| r.last_updated)),
+            ];
+            let schema = Schema::new(vec![
+                Field::new("uuid", DataType::Utf8, false),
+                Field::new("name", DataType::Utf8, false),
+                timestamp_field("last_seen"),
+                timestamp_field("last_updated"),
+            ]);
+            write_parquet(path, schema, columns)
+        }
+    }
+}
+
+fn timestamps(values: impl Iterator<Item = DateTime>) -> ArrayRef {
+    Arc::new(
+        TimestampMillisecondArray::from_iter_values(values.map(|d| d.timestamp_millis()))
+            .with_timezone("UTC"),
+    )
+}
+
+fn timestamp_field(name: &str) -> Field {
+    Field::new(
+        name,
+        DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+        false,
+    )
+}
+
+fn write_parquet(path: &str, schema: Schema, columns: Vec<ArrayRef>) -> io::Result<()> {
+    let schema = Arc::new(schema);
+    let batch = RecordBatch::try_new(schema.clone(), columns).map_err(io::Error::other)?;
+
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema, Some(properties))
+        .map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.close().map_err(io::Error::other)?;
+
+    Ok(())
+}
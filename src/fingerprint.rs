@@ -0,0 +1,314 @@
+//! Guesses which server implementation produced a status response.
+//!
+//! Every heuristic that matches adds its confidence to a candidate, the
+//! candidate with the highest total wins and ties go to the one matched
+//! first. The winner's evidence strings are kept so surprising results can be
+//! traced back to the response.
+
+use serde::{Deserialize, Serialize};
+
+use crate::login::LoginBehaviour;
+use crate::response::ResponseData;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Software {
+    Vanilla,
+    Paper,
+    Purpur,
+    Spigot,
+    Fabric,
+    Forge,
+    NeoForge,
+    Velocity,
+    BungeeCord,
+    Waterfall,
+    Geyser,
+    Minestom,
+    Unknown,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Fingerprint {
+    pub software: Software,
+    /// Between 0 and 1
+    pub confidence: f64,
+    pub evidence: Vec<String>,
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Self {
+            software: Software::Unknown,
+            confidence: 0.0,
+            evidence: Vec::new(),
+        }
+    }
+}
+
+/// Version name prefixes that software puts in front of the game version.
+const NAME_PREFIXES: [(&str, Software); 12] = [
+    ("purpur", Software::Purpur),
+    ("paper", Software::Paper),
+    ("folia", Software::Paper),
+    ("pufferfish", Software::Paper),
+    ("spigot", Software::Spigot),
+    ("craftbukkit", Software::Spigot),
+    ("velocity", Software::Velocity),
+    ("waterfall", Software::Waterfall),
+    ("bungeecord", Software::BungeeCord),
+    ("geyser", Software::Geyser),
+    ("minestom", Software::Minestom),
+    ("neoforge", Software::NeoForge),
+];
+
+/// Candidates in the order they were first matched.
+#[derive(Default)]
+struct Candidates(Vec<Fingerprint>);
+
+impl Candidates {
+    fn add(&mut self, software: Software, confidence: f64, evidence: String) {
+        match self.0.iter_mut().find(|c| c.software == software) {
+            Some(candidate) => {
+                candidate.confidence += confidence;
+                candidate.evidence.push(evidence);
+            }
+            None => self.0.push(Fingerprint {
+                software,
+                confidence,
+                evidence: vec![evidence],
+            }),
+        }
+    }
+
+    fn winner(self) -> Fingerprint {
+        let mut winner = Fingerprint::default();
+        for candidate in self.0 {
+            if candidate.confidence > winner.confidence {
+                winner = candidate;
+            }
+        }
+        winner.confidence = winner.confidence.min(1.0);
+        winner
+    }
+}
+
+/// Guesses the software from the status response and, if a login was
+/// attempted, how the server answered it.
+pub fn fingerprint(data: &ResponseData, login: Option<&LoginBehaviour>) -> Fingerprint {
+    let mut candidates = Candidates::default();
+
+    fingerprint_version_name(data, &mut candidates);
+    fingerprint_mods(data, &mut candidates);
+    if let Some(login) = login {
+        fingerprint_login(login, &mut candidates);
+    }
+    fingerprint_quirks(data, &mut candidates);
+
+    candidates.winner()
+}
+
+fn fingerprint_version_name(data: &ResponseData, candidates: &mut Candidates) {
+    let name = data.version.name.trim().to_lowercase();
+
+    for (prefix, software) in NAME_PREFIXES {
+        if name.starts_with(prefix) {
+            candidates.add(
+                software,
+                0.8,
+                format!("version name starts with {}", prefix),
+            );
+            return;
+        }
+    }
+    for (prefix, software) in NAME_PREFIXES {
+        if name.contains(prefix) {
+            candidates.add(software, 0.5, format!("version name contains {}", prefix));
+            return;
+        }
+    }
+
+    // Proxies commonly advertise a range like `1.8.x-1.20.x` and answer with
+    // whatever protocol the client asked for, which we send as -1
+    if name.contains('-') && name.contains(".x") {
+        candidates.add(
+            Software::BungeeCord,
+            0.4,
+            "version name is a range".to_owned(),
+        );
+    } else if data.version.protocol == -1 {
+        candidates.add(
+            Software::Velocity,
+            0.3,
+            "protocol echoed back as -1".to_owned(),
+        );
+    } else if is_plain_release(&name) {
+        candidates.add(
+            Software::Vanilla,
+            0.5,
+            "version name is a bare release".to_owned(),
+        );
+    }
+}
+
+fn fingerprint_mods(data: &ResponseData, candidates: &mut Candidates) {
+    if data.is_modded == Some(true) {
+        candidates.add(Software::NeoForge, 0.8, "isModded is set".to_owned());
+    }
+
+    match data.mod_info_type.as_deref() {
+        Some("FML") => candidates.add(Software::Forge, 0.7, "modinfo type is FML".to_owned()),
+        Some("BUKKIT") => {
+            candidates.add(Software::Spigot, 0.4, "modinfo type is BUKKIT".to_owned())
+        }
+        _ => {}
+    }
+
+    // Bukkit servers reuse the modinfo format, that's not forge data
+    if data.mod_info_type.as_deref() == Some("BUKKIT") {
+        return;
+    }
+
+    if let Some(forge_data) = &data.forge_data {
+        let forge_mods = forge_data.resolve();
+        if forge_mods.mods.iter().any(|m| m.mod_id == "neoforge") {
+            candidates.add(Software::NeoForge, 0.9, "neoforge mod present".to_owned());
+        } else if forge_mods.mods.iter().any(|m| m.mod_id == "fabricloader") {
+            candidates.add(Software::Fabric, 0.7, "fabricloader mod present".to_owned());
+        } else {
+            candidates.add(Software::Forge, 0.7, "forge data present".to_owned());
+        }
+    }
+}
+
+fn fingerprint_login(login: &LoginBehaviour, candidates: &mut Candidates) {
+    match login {
+        // BungeeCord and its forks send a random one, vanilla and Velocity
+        // an empty one
+        LoginBehaviour::Encryption { server_id } if !server_id.is_empty() => candidates.add(
+            Software::BungeeCord,
+            0.5,
+            "encryption request has a server id".to_owned(),
+        ),
+        LoginBehaviour::PluginRequest { channel } if channel == "fml:loginwrapper" => {
+            candidates.add(Software::Forge, 0.8, "FML login handshake".to_owned())
+        }
+        // Backends behind Velocity with modern forwarding, nearly always Paper
+        LoginBehaviour::PluginRequest { channel } if channel == "velocity:player_info" => {
+            candidates.add(
+                Software::Paper,
+                0.4,
+                "asks for Velocity forwarding data".to_owned(),
+            )
+        }
+        LoginBehaviour::Disconnect { reason } => {
+            let reason = reason.to_lowercase();
+            if reason.contains("ip forwarding") {
+                candidates.add(
+                    Software::Spigot,
+                    0.4,
+                    "asks for BungeeCord IP forwarding".to_owned(),
+                );
+            } else if reason.contains("connect with velocity") {
+                candidates.add(
+                    Software::Paper,
+                    0.4,
+                    "asks to connect through Velocity".to_owned(),
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+fn fingerprint_quirks(data: &ResponseData, candidates: &mut Candidates) {
+    // Minestom doesn't fill in a version name or player limit unless told to
+    if data.version.name.is_empty() && data.players.max == 0 {
+        candidates.add(
+            Software::Minestom,
+            0.2,
+            "empty version name and no player limit".to_owned(),
+        );
+    }
+
+    // NoChatReports adds preventsChatReports, on Forge it would also show up
+    // in forge data so without it the server is most likely Fabric
+    if data.prevents_chat_reports.is_some() && data.forge_data.is_none() {
+        candidates.add(
+            Software::Fabric,
+            0.3,
+            "preventsChatReports without forge data".to_owned(),
+        );
+    }
+}
+
+/// Whether the name looks like `1.20.1` and nothing else.
+fn is_plain_release(name: &str) -> bool {
+    let mut parts = name.split('.');
+    parts.next() == Some("1")
+        && parts.clone().count() >= 1
+        && parts.all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn status(version_name: &str, extra: serde_json::Value) -> ResponseData {
+        let mut status = json!({
+            "version": {"name": version_name, "protocol": 763},
+            "players": {"max": 20, "online": 0},
+            "description": "A Minecraft Server",
+        });
+        status
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        ResponseData::from_value(&status)
+    }
+
+    #[test]
+    fn keeps_only_the_winners_evidence() {
+        let data = status("Paper 1.20.1", json!({"preventsChatReports": true}));
+        let fingerprint = fingerprint(&data, None);
+
+        assert_eq!(fingerprint.software, Software::Paper);
+        assert_eq!(fingerprint.evidence, ["version name starts with paper"]);
+    }
+
+    #[test]
+    fn breaks_ties_by_first_match() {
+        // Both 0.4, the version name is looked at first
+        let data = status("1.8.x-1.20.x", json!({"modinfo": {"type": "BUKKIT"}}));
+
+        for _ in 0..20 {
+            let fingerprint = fingerprint(&data, None);
+            assert_eq!(fingerprint.software, Software::BungeeCord);
+            assert_eq!(fingerprint.evidence, ["version name is a range"]);
+        }
+    }
+
+    #[test]
+    fn uses_login_behaviour() {
+        let data = status("1.20.1", json!({}));
+
+        let login = LoginBehaviour::PluginRequest {
+            channel: "fml:loginwrapper".to_owned(),
+        };
+        assert_eq!(fingerprint(&data, Some(&login)).software, Software::Forge);
+
+        let login = LoginBehaviour::Encryption {
+            server_id: String::new(),
+        };
+        assert_eq!(fingerprint(&data, Some(&login)).software, Software::Vanilla);
+
+        let data = status("1.8.x-1.20.x", json!({}));
+        let login = LoginBehaviour::Encryption {
+            server_id: "-3fa2b9c0d1e47".to_owned(),
+        };
+        let fingerprint = fingerprint(&data, Some(&login));
+        assert_eq!(fingerprint.software, Software::BungeeCord);
+        assert_eq!(fingerprint.confidence, 0.9);
+    }
+}
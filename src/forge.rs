@@ -0,0 +1,282 @@
+//! Decoder for the compressed `forgeData.d` field sent by Forge 1.18+ (FML3).
+//!
+//! The payload is a byte buffer packed into a string, 15 bits per UTF-16 unit,
+//! prefixed by two units holding the byte length. The buffer itself contains
+//! the mod list and channel list that no longer fit in the plain JSON fields.
+
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::response::Mod;
+
+/// Version reported for mods that are flagged as server-only.
+pub const IGNORE_SERVER_ONLY: &str = "IGNORESERVERONLY";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ForgeChannel {
+    #[serde(alias = "res")]
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Mods and channels recovered from any kind of forge data.
+#[derive(Debug, Clone, Default)]
+pub struct ForgeMods {
+    pub truncated: bool,
+    pub mods: Vec<Mod>,
+    pub channels: Vec<ForgeChannel>,
+}
+
+/// Decodes an FML3 `d` string.
+///
+/// Returns `None` if the string is not a valid payload at all. A payload that
+/// ends early yields everything read up to that point with `truncated` set.
+pub fn decode_optimized(d: &str) -> Option<ForgeMods> {
+    let bytes = unpack_bytes(d)?;
+    let mut reader = ByteReader::new(&bytes);
+
+    let mut forge_mods = ForgeMods {
+        truncated: reader.read_bool()?,
+        ..Default::default()
+    };
+
+    let mod_count = reader.read_u16()?;
+    for _ in 0..mod_count {
+        if read_mod(&mut reader, &mut forge_mods).is_none() {
+            forge_mods.truncated = true;
+            return Some(forge_mods);
+        }
+    }
+
+    let channel_count = match reader.read_varint() {
+        Some(count) => count,
+        None => {
+            forge_mods.truncated = true;
+            return Some(forge_mods);
+        }
+    };
+    for _ in 0..channel_count {
+        match read_channel(&mut reader, None) {
+            Some(channel) => forge_mods.channels.push(channel),
+            None => {
+                forge_mods.truncated = true;
+                break;
+            }
+        }
+    }
+
+    Some(forge_mods)
+}
+
+fn read_mod(reader: &mut ByteReader, forge_mods: &mut ForgeMods) -> Option<()> {
+    let flags = reader.read_varint()?;
+    let channel_count = flags >> 1;
+    let ignore_server_only = flags & 0b1 != 0;
+
+    let mod_id = reader.read_string()?;
+    let mod_marker = if ignore_server_only {
+        IGNORE_SERVER_ONLY.to_owned()
+    } else {
+        reader.read_string()?
+    };
+
+    for _ in 0..channel_count {
+        let channel = read_channel(reader, Some(&mod_id))?;
+        forge_mods.channels.push(channel);
+    }
+
+    forge_mods.mods.push(Mod { mod_id, mod_marker });
+    Some(())
+}
+
+fn read_channel(reader: &mut ByteReader, namespace: Option<&str>) -> Option<ForgeChannel> {
+    let path = reader.read_string()?;
+    let version = reader.read_string()?;
+    let required = reader.read_bool()?;
+
+    let name = match namespace {
+        Some(namespace) => format!("{}:{}", namespace, path),
+        None => path,
+    };
+
+    Some(ForgeChannel {
+        name,
+        version,
+        required,
+    })
+}
+
+fn unpack_bytes(d: &str) -> Option<Vec<u8>> {
+    let units: Vec<u16> = d.encode_utf16().collect();
+    if units.len() < 2 {
+        return None;
+    }
+
+    let size = (units[0] as usize & 0x7FFF) | ((units[1] as usize & 0x7FFF) << 15);
+    // Each unit carries 15 bits, so anything claiming more is not a real payload
+    if size > (units.len() - 2) * 15 / 8 + 1 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(size);
+    let mut buffer: u32 = 0;
+    let mut bits_in_buffer = 0;
+
+    for unit in &units[2..] {
+        while bits_in_buffer >= 8 {
+            bytes.push(buffer as u8);
+            buffer >>= 8;
+            bits_in_buffer -= 8;
+        }
+        buffer |= (*unit as u32 & 0x7FFF) << bits_in_buffer;
+        bits_in_buffer += 15;
+    }
+
+    while bytes.len() < size {
+        bytes.push(buffer as u8);
+        buffer >>= 8;
+    }
+    bytes.truncate(size);
+
+    Some(bytes)
+}
+
+/// Bounds-checked reader, unlike the helpers in `types` this never panics.
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn read_bool(&mut self) -> Option<bool> {
+        self.read_u8().map(|b| b != 0)
+    }
+
+    fn read_u16(&mut self) -> Option<u16> {
+        let high = self.read_u8()? as u16;
+        let low = self.read_u8()? as u16;
+        Some((high << 8) | low)
+    }
+
+    fn read_varint(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+        for position in (0..35).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= (byte as u32 & 0x7F) << position;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn read_string(&mut self) -> Option<String> {
+        let len = self.read_varint()? as usize;
+        let end = self.position.checked_add(len)?;
+        let raw = self.bytes.get(self.position..end)?;
+        self.position = end;
+        str::from_utf8(raw).ok().map(|s| s.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `forgeData.d` of a Forge 47.2.0 server with jei and a server-only spark,
+    /// laid out and packed the way FML3 does.
+    const FML3_D: &str = "\u{9e}\u{0}\u{0}\u{8}\u{3424}\u{734b}\u{3656}\u{2e4c}\u{1998}\u{33a}\u{2e31}\u{6064}\u{44b8}\u{2821}\u{7660}\u{6e4d}\u{1959}\u{1a03}\u{2e37}\u{5c64}\u{30c0}\u{4ba0}\u{2656}\u{6bee}\u{1bdc}\u{3a39}\u{6e69}\u{6ce}\u{38c4}\u{181}\u{3050}\u{e0e}\u{1a5b}\u{1ba}\u{2e31}\u{262}\u{c08}\u{2b50}\u{1696}\u{2621}\u{b8d}\u{1719}\u{2e30}\u{6e64}\u{c1c}\u{b43}\u{66e6}\u{cad}\u{425b}\u{1a98}\u{322e}\u{605c}\u{48b8}\u{9b9}\u{5010}\u{e60}\u{185c}\u{35b9}\u{1202}\u{52da}\u{15b9}\u{131b}\u{6617}\u{4e8c}\u{5c8e}\u{33b2}\u{7369}\u{4ae8}\u{11c9}\u{6a30}\u{34c4}\u{6}\u{5b45}\u{3734}\u{6365}\u{42e4}\u{5199}\u{29d3}\u{26e7}\u{6cae}\u{5a59}\u{3a39}\u{7265}\u{c08}\u{3135}\u{19a}\u{0}";
+
+    /// Packs bytes 15 bits per unit behind the length, the inverse of
+    /// `unpack_bytes`.
+    fn pack_bytes(bytes: &[u8]) -> String {
+        let mut units = vec![
+            (bytes.len() & 0x7FFF) as u16,
+            ((bytes.len() >> 15) & 0x7FFF) as u16,
+        ];
+        let mut buffer: u32 = 0;
+        let mut bits_in_buffer = 0;
+        for byte in bytes {
+            if bits_in_buffer >= 15 {
+                units.push((buffer & 0x7FFF) as u16);
+                buffer >>= 15;
+                bits_in_buffer -= 15;
+            }
+            buffer |= (*byte as u32) << bits_in_buffer;
+            bits_in_buffer += 8;
+        }
+        while bits_in_buffer > 0 {
+            units.push((buffer & 0x7FFF) as u16);
+            buffer >>= 15;
+            bits_in_buffer -= 15.min(bits_in_buffer);
+        }
+        String::from_utf16(&units).unwrap()
+    }
+
+    #[test]
+    fn decodes_fml3_payload() {
+        let forge_mods = decode_optimized(FML3_D).unwrap();
+
+        assert!(!forge_mods.truncated);
+        let mods: Vec<(&str, &str)> = forge_mods
+            .mods
+            .iter()
+            .map(|m| (m.mod_id.as_str(), m.mod_marker.as_str()))
+            .collect();
+        assert_eq!(
+            mods,
+            [
+                ("minecraft", "1.20.1"),
+                ("forge", "47.2.0"),
+                ("jei", "15.2.0.27"),
+                ("spark", IGNORE_SERVER_ONLY),
+            ]
+        );
+
+        let channels: Vec<(&str, &str, bool)> = forge_mods
+            .channels
+            .iter()
+            .map(|c| (c.name.as_str(), c.version.as_str(), c.required))
+            .collect();
+        assert_eq!(
+            channels,
+            [
+                ("forge:tier_sorting", "1.0", false),
+                ("forge:split", "1.1", true),
+                ("jei:channel", "15.2.0.27", true),
+                ("minecraft:register", "FML3", false),
+                ("minecraft:unregister", "FML3", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn unpacks_every_length() {
+        for len in 0..64 {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 37 + 0xA5) as u8).collect();
+            assert_eq!(unpack_bytes(&pack_bytes(&bytes)).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn flags_truncated_payload() {
+        let bytes = unpack_bytes(FML3_D).unwrap();
+        let forge_mods = decode_optimized(&pack_bytes(&bytes[..40])).unwrap();
+
+        assert!(forge_mods.truncated);
+        assert_eq!(forge_mods.mods[0].mod_id, "minecraft");
+    }
+}
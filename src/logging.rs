@@ -0,0 +1,44 @@
+//! Log output on stderr, as text for people or JSON lines for ingestion.
+
+use clap::ValueEnum;
+use tracing_subscriber::filter::{EnvFilter, ParseError};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Level used when neither `--log-level` nor `RUST_LOG` is set.
+const DEFAULT_LEVEL: &str = "info";
+
+/// Installs the global subscriber. `level` takes `RUST_LOG` style directives,
+/// and every probe of a host in `trace_hosts` is logged in full on top of it.
+pub fn init(
+    level: Option<&str>,
+    format: LogFormat,
+    trace_hosts: &[String],
+) -> Result<(), ParseError> {
+    let mut directives = match level {
+        Some(level) => level.to_owned(),
+        None => std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_else(|_| DEFAULT_LEVEL.to_owned()),
+    };
+    for host in trace_hosts {
+        directives += &format!(",[probe{{host={}}}]=trace", host);
+    }
+    let filter = EnvFilter::try_new(directives)?;
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+    }
+
+    Ok(())
+}
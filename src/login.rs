@@ -0,0 +1,256 @@
+//! Login attempt that stops before joining, for telling server software apart
+//! by how it answers. Only made when asked for, servers log it as a failed
+//! join.
+
+use std::io::{self, Read};
+use std::slice::Iter;
+use std::time::Duration;
+
+use flate2::read::ZlibDecoder;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::time::timeout;
+use tracing::trace;
+
+use crate::packet::{handshake_login_packet, login_start};
+use crate::response::Response;
+use crate::types::VarInt;
+
+/// Name logged in with. Offline mode servers let it in, the connection is
+/// closed before it joins.
+const USERNAME: &str = "ServerSentry";
+
+/// Offline mode uuid of `USERNAME`.
+const PLAYER_UUID: [u8; 16] = [
+    0x7a, 0x37, 0xa1, 0x76, 0xb7, 0x7c, 0x30, 0x52, 0xbe, 0x28, 0x46, 0x0e, 0x47, 0x3a, 0x2a, 0x11,
+];
+
+/// Protocol logged in with when the server echoed ours back, 1.20.1.
+const FALLBACK_PROTOCOL: i32 = 763;
+
+/// Largest login packet read. Forge plugin requests carry the mod list.
+const MAX_PACKET_LEN: usize = 1024 * 1024;
+
+/// Packets read looking for an answer, compression may be set up first.
+const MAX_PACKETS: usize = 4;
+
+/// How the server answered a login.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LoginBehaviour {
+    /// Asked for encryption, vanilla sends an empty server id
+    Encryption { server_id: String },
+    /// Let an unauthenticated player in
+    Success,
+    /// Sent a login plugin request on this channel
+    PluginRequest { channel: String },
+    /// Refused the login with this chat component
+    Disconnect { reason: String },
+}
+
+/// Attempts a login with the server that sent `response`, as the protocol it
+/// reported, and keeps the answer on it. A failed attempt leaves it unset.
+pub async fn probe_login(response: &mut Response, phase_timeout: Duration) {
+    let data = &response.data;
+    let (host, port, protocol) = (data.host.as_str(), data.port, data.version.protocol);
+    let login = match response.peer {
+        Some(peer) => attempt_login(peer, host, port, protocol, phase_timeout).await,
+        None => {
+            let address = format!("{}:{}", host, port);
+            attempt_login(address, host, port, protocol, phase_timeout).await
+        }
+    };
+    match login {
+        Ok(login) => response.login = Some(login),
+        Err(err) => trace!(error = %err, "login attempt failed"),
+    }
+}
+
+/// Connects to `address` and starts a login with `ip` as `protocol`, then
+/// returns the first real answer. Allows up to `phase_timeout` for connecting
+/// and for the rest.
+pub async fn attempt_login(
+    address: impl ToSocketAddrs,
+    ip: &str,
+    port: i16,
+    protocol: i32,
+    phase_timeout: Duration,
+) -> io::Result<LoginBehaviour> {
+    let protocol = if protocol > 0 {
+        protocol
+    } else {
+        FALLBACK_PROTOCOL
+    };
+    let mut stream = timeout(phase_timeout, TcpStream::connect(address)).await??;
+
+    timeout(phase_timeout, async {
+        stream
+            .write_all(&handshake_login_packet(ip, port, protocol).to_bytes())
+            .await?;
+        stream
+            .write_all(&login_start(USERNAME, &PLAYER_UUID, protocol).to_bytes())
+            .await?;
+        stream.flush().await?;
+        read_answer(&mut stream).await
+    })
+    .await?
+}
+
+/// Reads login packets until one that says something about the server.
+async fn read_answer(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<LoginBehaviour> {
+    let mut compression = false;
+
+    for _ in 0..MAX_PACKETS {
+        let (packet_id, body) = read_packet(stream, compression).await?;
+        let mut body = body.iter();
+        trace!(packet_id, "read login packet");
+
+        let behaviour = match packet_id {
+            0x00 => LoginBehaviour::Disconnect {
+                reason: read_string(&mut body)?,
+            },
+            0x01 => LoginBehaviour::Encryption {
+                server_id: read_string(&mut body)?,
+            },
+            0x02 => LoginBehaviour::Success,
+            0x03 => {
+                compression = true;
+                continue;
+            }
+            0x04 => {
+                read_varint(&mut body)?;
+                LoginBehaviour::PluginRequest {
+                    channel: read_string(&mut body)?,
+                }
+            }
+            other => return Err(invalid(format!("unexpected login packet {}", other))),
+        };
+        return Ok(behaviour);
+    }
+
+    Err(invalid("no answer to login".to_owned()))
+}
+
+/// Reads one packet, inflating it once compression is on.
+async fn read_packet(
+    stream: &mut (impl AsyncRead + Unpin),
+    compression: bool,
+) -> io::Result<(i32, Vec<u8>)> {
+    let len = read_length(stream).await?;
+    let mut frame = vec![0; len];
+    stream.read_exact(&mut frame).await?;
+
+    let mut bytes = frame.iter();
+    let body = if compression && read_varint(&mut bytes)? != 0 {
+        let mut inflated = Vec::new();
+        ZlibDecoder::new(bytes.as_slice())
+            .take(MAX_PACKET_LEN as u64)
+            .read_to_end(&mut inflated)?;
+        inflated
+    } else {
+        bytes.as_slice().to_vec()
+    };
+
+    let mut body = body.iter();
+    let packet_id = read_varint(&mut body)?;
+    Ok((packet_id, body.as_slice().to_vec()))
+}
+
+async fn read_length(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<usize> {
+    let mut value = 0u32;
+    for position in 0..5 {
+        let byte = stream.read_u8().await?;
+        value |= (byte as u32 & 0x7F) << (7 * position);
+        if byte & 0x80 == 0 {
+            let len = value as usize;
+            if len > MAX_PACKET_LEN {
+                return Err(invalid(format!("{} byte login packet", len)));
+            }
+            return Ok(len);
+        }
+    }
+    Err(invalid("length prefix too long".to_owned()))
+}
+
+fn read_varint(bytes: &mut Iter<u8>) -> io::Result<i32> {
+    VarInt::try_parse(bytes).ok_or_else(|| invalid("truncated varint".to_owned()))
+}
+
+fn read_string(bytes: &mut Iter<u8>) -> io::Result<String> {
+    let len = read_varint(bytes)? as usize;
+    let raw = bytes
+        .as_slice()
+        .get(..len)
+        .ok_or_else(|| invalid("truncated string".to_owned()))?;
+    let string = String::from_utf8_lossy(raw).into_owned();
+    *bytes = bytes.as_slice()[len..].iter();
+    Ok(string)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    use super::*;
+
+    fn frame(body: &[u8]) -> Vec<u8> {
+        [VarInt::new(body.len() as i32).bytes, body.to_vec()].concat()
+    }
+
+    fn string(value: &str) -> Vec<u8> {
+        [
+            VarInt::new(value.len() as i32).bytes,
+            value.as_bytes().to_vec(),
+        ]
+        .concat()
+    }
+
+    #[tokio::test]
+    async fn reads_encryption_request() {
+        let body = [vec![0x01], string("1a2b3c"), vec![0, 0]].concat();
+        let answer = read_answer(&mut frame(&body).as_slice()).await.unwrap();
+
+        assert_eq!(
+            answer,
+            LoginBehaviour::Encryption {
+                server_id: "1a2b3c".to_owned()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_compressed_plugin_request() {
+        let set_compression = frame(&[0x03, 0x00]);
+        let request = [vec![0x04, 0x00], string("fml:loginwrapper"), vec![0; 300]].concat();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&request).unwrap();
+        let compressed = [
+            VarInt::new(request.len() as i32).bytes,
+            encoder.finish().unwrap(),
+        ]
+        .concat();
+        let stream = [set_compression, frame(&compressed)].concat();
+
+        let answer = read_answer(&mut stream.as_slice()).await.unwrap();
+        assert_eq!(
+            answer,
+            LoginBehaviour::PluginRequest {
+                channel: "fml:loginwrapper".to_owned()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_disconnect() {
+        let body = [vec![0x00, 0x20], b"{\"text\"".to_vec()].concat();
+        assert!(read_answer(&mut frame(&body).as_slice()).await.is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Prometheus metrics, served on `/metrics` by the long running commands.
+//!
+//! Probe and storage metrics are recorded where the work happens. The
+//! ecosystem gauges are refreshed from storage, so they describe every
+//! server's latest probe whichever process made it.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tracing::{error, info};
+
+use crate::protocol::{releases, Edition};
+use crate::storage::Storage;
+
+pub struct Metrics {
+    registry: Registry,
+    /// Finished probes by outcome, `online` or a failure class
+    pub probes: IntCounterVec,
+    /// Time spent in each phase of a probe, including ones that failed
+    pub phase_duration: HistogramVec,
+    pub probes_in_flight: IntGauge,
+    /// Targets waiting for their turn to be probed
+    pub probe_queue: IntGauge,
+    /// Time to store one batch from the write buffer
+    pub storage_write_duration: Histogram,
+    pub storage_write_errors: IntCounter,
+    /// Results waiting in the write buffer
+    pub write_queue: IntGauge,
+    pub servers_online: IntGauge,
+    pub players_online: IntGauge,
+    pub version_servers: IntGaugeVec,
+    pub version_players: IntGaugeVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("sentry".to_owned()), None).unwrap();
+        // 5ms up to about 20s
+        let buckets = exponential_buckets(0.005, 2.0, 13).unwrap();
+
+        let metrics = Self {
+            probes: IntCounterVec::new(
+                Opts::new("probes_total", "Finished probes by outcome"),
+                &["outcome"],
+            )
+            .unwrap(),
+            phase_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "probe_phase_duration_seconds",
+                    "Time spent in each phase of a probe",
+                )
+                .buckets(buckets.clone()),
+                &["phase"],
+            )
+            .unwrap(),
+            probes_in_flight: IntGauge::new("probes_in_flight", "Probes running right now")
+                .unwrap(),
+            probe_queue: IntGauge::new(
+                "probe_queue_depth",
+                "Targets waiting for their turn to be probed",
+            )
+            .unwrap(),
+            storage_write_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "storage_write_duration_seconds",
+                    "Time to store one batch of results",
+                )
+                .buckets(buckets),
+            )
+            .unwrap(),
+            storage_write_errors: IntCounter::new(
+                "storage_write_errors_total",
+                "Results that failed to store",
+            )
+            .unwrap(),
+            write_queue: IntGauge::new("write_queue_depth", "Results waiting in the write buffer")
+                .unwrap(),
+            servers_online: IntGauge::new("servers_online", "Servers online at their last probe")
+                .unwrap(),
+            players_online: IntGauge::new(
+                "players_online",
+                "Players on the servers online at their last probe",
+            )
+            .unwrap(),
+            version_servers: IntGaugeVec::new(
+                Opts::new(
+                    "version_servers_online",
+                    "Online servers per protocol version",
+                ),
+                &["protocol", "version"],
+            )
+            .unwrap(),
+            version_players: IntGaugeVec::new(
+                Opts::new(
+                    "version_players_online",
+                    "Online players per protocol version",
+                ),
+                &["protocol", "version"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.probes.clone())).unwrap();
+        registry
+            .register(Box::new(metrics.phase_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.probes_in_flight.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.probe_queue.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.storage_write_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.storage_write_errors.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.write_queue.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.servers_online.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.players_online.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.version_servers.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.version_players.clone()))
+            .unwrap();
+
+        metrics
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn render(&self) -> prometheus::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+
+    /// Sets the ecosystem gauges from what storage knows now.
+    async fn refresh_ecosystem(&self, storage: &dyn Storage) {
+        let counts = match storage.online_counts().await {
+            Ok(counts) => counts,
+            Err(err) => {
+                error!(error = %err, "error counting online servers");
+                return;
+            }
+        };
+
+        self.version_servers.reset();
+        self.version_players.reset();
+        let (mut servers, mut players) = (0, 0);
+        for count in counts {
+            let protocol = count.protocol.to_string();
+            let version = releases(Edition::Java, count.protocol)
+                .last()
+                .copied()
+                .unwrap_or("unknown");
+            self.version_servers
+                .with_label_values(&[protocol.as_str(), version])
+                .set(count.servers);
+            self.version_players
+                .with_label_values(&[protocol.as_str(), version])
+                .set(count.players);
+            servers += count.servers;
+            players += count.players;
+        }
+        self.servers_online.set(servers);
+        self.players_online.set(players);
+    }
+}
+
+/// Routes serving `/metrics`.
+pub fn router() -> Router {
+    Router::new().route("/metrics", get(render))
+}
+
+async fn render() -> impl IntoResponse {
+    match metrics().render() {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+            body,
+        ),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain")],
+            err.to_string(),
+        ),
+    }
+}
+
+/// Refreshes the ecosystem gauges every `refresh`, forever.
+pub async fn refresh_ecosystem(storage: Arc<dyn Storage>, refresh: Duration) {
+    let mut ticker = tokio::time::interval(refresh);
+    loop {
+        ticker.tick().await;
+        metrics().refresh_ecosystem(storage.as_ref()).await;
+    }
+}
+
+/// Serves `/metrics` on `addr` and refreshes the ecosystem gauges every
+/// `refresh`. Runs until the task is aborted.
+pub async fn serve(addr: SocketAddr, storage: Arc<dyn Storage>, refresh: Duration) {
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(error = %err, %addr, "error binding metrics endpoint");
+            return;
+        }
+    };
+    info!(%addr, "serving metrics");
+
+    tokio::select! {
+        _ = refresh_ecosystem(storage, refresh) => {}
+        result = axum::serve(listener, router()) => {
+            if let Err(err) = result {
+                error!(error = %err, "error serving metrics");
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::probe::{FailureKind, ProbeError};
+
+use super::server::ServerStatus;
+
+/// Probe that didn't end in a usable status response.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProbeFailure {
+    pub host: String,
+    pub port: i16,
+    pub observed_at: DateTime,
+    /// `FailureKind::as_str` of what went wrong
+    pub error: String,
+    pub message: String,
+    /// Status the server moves to, unless it has failed often enough to be
+    /// considered gone
+    pub status: ServerStatus,
+}
+
+impl ProbeFailure {
+    pub fn new(host: &str, port: i16, observed_at: DateTime, err: &ProbeError) -> Self {
+        Self {
+            host: host.to_owned(),
+            port,
+            observed_at,
+            error: err.kind.as_str().to_owned(),
+            message: err.message.clone(),
+            status: match err.kind {
+                FailureKind::NotMinecraft => ServerStatus::NotMinecraft,
+                _ => ServerStatus::Unreachable,
+            },
+        }
+    }
+}
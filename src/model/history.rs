@@ -0,0 +1,58 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::failure::ProbeFailure;
+use super::observation::{content_hash, ServerObservation};
+
+/// Entry in the append-only `observations` collection, one per probe of a
+/// known server. Only `online` entries have the status fields set, only
+/// offline ones have `error`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    pub host: String,
+    pub port: i16,
+    pub observed_at: DateTime,
+    pub online: bool,
+    pub online_players: Option<i32>,
+    pub max_players: Option<i32>,
+    pub latency_ms: Option<i32>,
+    pub protocol: Option<i32>,
+    pub version_name: Option<String>,
+    pub motd_hash: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn online(observation: &ServerObservation) -> Self {
+        Self {
+            host: observation.host.clone(),
+            port: observation.port,
+            observed_at: observation.observed_at,
+            online: true,
+            online_players: Some(observation.online.players),
+            max_players: Some(observation.online.max),
+            latency_ms: Some(observation.latency_ms),
+            protocol: Some(observation.version.protocol),
+            version_name: Some(observation.version.name.clone()),
+            motd_hash: Some(content_hash(&observation.motd)),
+            error: None,
+        }
+    }
+
+    pub fn offline(failure: &ProbeFailure) -> Self {
+        Self {
+            host: failure.host.clone(),
+            port: failure.port,
+            observed_at: failure.observed_at,
+            online: false,
+            online_players: None,
+            max_players: None,
+            latency_ms: None,
+            protocol: None,
+            version_name: None,
+            motd_hash: None,
+            error: Some(failure.error.clone()),
+        }
+    }
+}
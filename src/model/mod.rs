@@ -1,4 +1,10 @@
+pub mod failure;
+pub mod history;
+pub mod mods;
+pub mod observation;
 pub mod packets;
 pub mod player;
+pub mod raw;
 pub mod server;
+pub mod session;
 pub mod uuid;
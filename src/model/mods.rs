@@ -0,0 +1,89 @@
+use std::cmp::Ordering;
+
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::response::Mod;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ServerMod {
+    pub mod_id: String,
+    pub version: String,
+}
+
+impl From<Mod> for ServerMod {
+    fn from(value: Mod) -> Self {
+        Self {
+            mod_id: value.mod_id,
+            version: value.mod_marker,
+        }
+    }
+}
+
+/// Entry in the `mods` collection, one per mod per server.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModInstall {
+    pub mod_id: String,
+    pub version: String,
+    pub host: String,
+    pub port: i16,
+    pub first_seen: DateTime,
+    pub last_seen: DateTime,
+}
+
+/// Compares two mod version strings segment by segment.
+///
+/// Numeric segments are compared as numbers, everything else lexicographically,
+/// so `1.10.2` sorts after `1.9.4` and `43.2.0` after `43.1.7`.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a_segments = version_segments(a);
+    let mut b_segments = version_segments(b);
+
+    loop {
+        match (a_segments.next(), b_segments.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => {
+                let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    (Ok(_), Err(_)) => Ordering::Greater,
+                    (Err(_), Ok(_)) => Ordering::Less,
+                    (Err(_), Err(_)) => a.cmp(b),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+fn version_segments(version: &str) -> impl Iterator<Item = &str> {
+    version
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|s| !s.is_empty())
+}
+
+/// Inclusive version range used when searching servers by mod.
+#[derive(Debug, Clone, Default)]
+pub struct VersionRange {
+    pub min: Option<String>,
+    pub max: Option<String>,
+}
+
+impl VersionRange {
+    pub fn contains(&self, version: &str) -> bool {
+        if let Some(min) = &self.min {
+            if compare_versions(version, min) == Ordering::Less {
+                return false;
+            }
+        }
+        if let Some(max) = &self.max {
+            if compare_versions(version, max) == Ordering::Greater {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use mongodb::bson::DateTime;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    fingerprint::{fingerprint, Fingerprint},
+    forge::ForgeChannel,
+    protocol::{self, Edition, ResolvedVersion},
+    response::{Player, Response, Version},
+};
+
+use super::{
+    mods::ServerMod,
+    player::OnlinePlayer,
+    server::{Online, StatusExtension},
+};
+
+/// Everything learned about a server from a single status response, ready to
+/// be handed to a storage backend.
+#[derive(Debug, Clone)]
+pub struct ServerObservation {
+    pub host: String,
+    pub port: i16,
+    pub observed_at: DateTime,
+    /// Time from connecting to having the full status response
+    pub latency_ms: i32,
+    pub online: Online,
+    /// Player sample with names, `online` only keeps the uuids
+    pub players: Vec<Player>,
+    pub motd: String,
+    /// Base64 data URI as sent by the server
+    pub favicon: Option<String>,
+    pub version: Version,
+    pub resolved_version: ResolvedVersion,
+    pub software: Fingerprint,
+    pub forge: bool,
+    pub mods: Vec<ServerMod>,
+    pub mods_truncated: bool,
+    pub forge_channels: Vec<ForgeChannel>,
+    pub enforces_secure_chat: bool,
+    pub previews_chat: Option<bool>,
+    pub prevents_chat_reports: Option<bool>,
+    pub is_modded: Option<bool>,
+    pub mod_info_type: Option<String>,
+    pub extensions: Vec<StatusExtension>,
+    pub parse_warnings: Vec<String>,
+    /// Status JSON, only kept when raw responses are being stored
+    pub raw: Option<String>,
+}
+
+impl ServerObservation {
+    pub fn from_response(response: &Response, latency: Duration, keep_raw: bool) -> Self {
+        let data = &response.data;
+        let observed_at = DateTime::now();
+
+        // Storage carries `online_since` over for players already online
+        let online_players = data.players.list.iter().map(|player| OnlinePlayer {
+            uuid: player.id.clone(),
+            online_since: observed_at,
+        });
+        let online = Online {
+            max: data.players.max,
+            players: data.players.online,
+            list: HashSet::from_iter(online_players),
+        };
+
+        let forge_mods = data
+            .forge_data
+            .as_ref()
+            .map(|forge_data| forge_data.resolve())
+            .unwrap_or_default();
+
+        let extensions = data
+            .extensions
+            .iter()
+            .map(|(key, value)| StatusExtension {
+                key: key.clone(),
+                value: value.to_string(),
+            })
+            .collect();
+
+        Self {
+            host: data.host.clone(),
+            port: data.port,
+            observed_at,
+            latency_ms: latency.as_millis().min(i32::MAX as u128) as i32,
+            online,
+            players: data.players.list.clone(),
+            motd: data.description.text(),
+            favicon: data.favicon.clone(),
+            version: data.version.clone(),
+            resolved_version: protocol::resolve(
+                Edition::Java,
+                &data.version.name,
+                data.version.protocol,
+            ),
+            software: fingerprint(data, response.login.as_ref()),
+            forge: data.forge_data.is_some(),
+            mods: forge_mods.mods.into_iter().map(ServerMod::from).collect(),
+            mods_truncated: forge_mods.truncated,
+            forge_channels: forge_mods.channels,
+            enforces_secure_chat: data.enforces_secure_chat,
+            previews_chat: data.previews_chat,
+            prevents_chat_reports: data.prevents_chat_reports,
+            is_modded: data.is_modded,
+            mod_info_type: data.mod_info_type.clone(),
+            extensions,
+            parse_warnings: data.parse_warnings.clone(),
+            raw: keep_raw.then(|| response.raw.to_string()),
+        }
+    }
+
+    /// Sampled players, without the placeholders of a hidden sample.
+    pub fn known_players(&self) -> impl Iterator<Item = &Player> {
+        self.players
+            .iter()
+            .filter(|player| !player.id.is_anonymous())
+    }
+
+    /// Online players, without the placeholders of a hidden sample.
+    pub fn known_online(&self) -> impl Iterator<Item = &OnlinePlayer> {
+        self.online
+            .list
+            .iter()
+            .filter(|player| !player.uuid.is_anonymous())
+    }
+}
+
+/// Hex SHA-256 of some text, used to dedupe favicons and MOTDs.
+pub fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -3,7 +3,6 @@ use std::slice::Iter;
 use tokio::net::TcpStream;
 
 use crate::{
-    model::uuid::UUID,
     packet::Packet,
     types::{parse_bool, parse_bytes, parse_string, parse_varint},
 };
@@ -17,12 +16,12 @@ pub struct EncryptionRequestPacket {
 }
 
 impl EncryptionRequestPacket {
-    const packet_id: i32 = 0x01;
+    const PACKET_ID: i32 = 0x01;
 
     pub async fn parse(stream: &mut TcpStream) -> std::io::Result<Self> {
         let packet = Packet::from_stream(stream).await?;
 
-        assert_eq!(Self::packet_id, packet.packet_id);
+        assert_eq!(Self::PACKET_ID, packet.packet_id);
         let mut bytes_iter = packet.bytes.iter();
 
         let server_id = parse_string(&mut bytes_iter);
@@ -49,12 +48,12 @@ pub struct LoginSuccessPacket {
 }
 
 impl LoginSuccessPacket {
-    const packet_id: i32 = 0x02;
+    const PACKET_ID: i32 = 0x02;
 
     pub async fn parse(stream: &mut TcpStream) -> std::io::Result<Self> {
         let packet = Packet::from_stream(stream).await?;
 
-        assert_eq!(Self::packet_id, packet.packet_id);
+        assert_eq!(Self::PACKET_ID, packet.packet_id);
         let mut bytes_iter = packet.bytes.iter();
 
         let uuid = parse_string(&mut bytes_iter);
@@ -62,7 +61,7 @@ impl LoginSuccessPacket {
         let number_of_properties = parse_varint(&mut bytes_iter);
         let mut properties = Vec::new();
 
-        for i in 0..number_of_properties {
+        for _ in 0..number_of_properties {
             properties.push(Property::parse(&mut bytes_iter)?);
         }
 
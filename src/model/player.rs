@@ -1,13 +1,8 @@
-use std::{
-    hash::Hash,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::hash::Hash;
 
 use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
 
-use crate::response::Player;
-
 use super::uuid::UUID;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,41 +27,26 @@ impl Hash for MinecraftPlayer {
     }
 }
 
+/// Entry in the `server_players` collection linking a player to a server
+/// they've been seen on.
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct HistoricPlayer {
+pub struct ServerPlayer {
+    pub host: String,
+    pub port: i16,
     pub uuid: UUID,
+    pub first_seen: DateTime,
     pub last_seen: DateTime,
-}
-
-impl PartialEq for HistoricPlayer {
-    fn eq(&self, other: &Self) -> bool {
-        self.uuid == other.uuid
-    }
-}
-
-impl Eq for HistoricPlayer {}
-
-impl Hash for HistoricPlayer {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.uuid.hash(state);
-    }
+    /// Number of scans the player showed up in
+    pub times_seen: i32,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OnlinePlayer {
     pub uuid: UUID,
+    /// Start of the player's current session, see `SessionChanges`
     pub online_since: DateTime,
 }
 
-impl From<Player> for OnlinePlayer {
-    fn from(value: Player) -> Self {
-        Self {
-            uuid: value.id,
-            online_since: DateTime::now(),
-        }
-    }
-}
-
 impl PartialEq for OnlinePlayer {
     fn eq(&self, other: &Self) -> bool {
         self.uuid == other.uuid
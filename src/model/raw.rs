@@ -0,0 +1,12 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+/// Status JSON as received, kept so responses can be reprocessed later.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RawResponse {
+    pub host: String,
+    pub port: i16,
+    pub received: DateTime,
+    /// Stored as a string since status keys aren't guaranteed to be valid BSON keys
+    pub status: String,
+}
@@ -3,9 +3,11 @@ use std::collections::HashSet;
 use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
 
-use crate::response::Version;
+use crate::{
+    fingerprint::Fingerprint, forge::ForgeChannel, protocol::ResolvedVersion, response::Version,
+};
 
-use super::player::{HistoricPlayer, OnlinePlayer};
+use super::{mods::ServerMod, player::OnlinePlayer};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MinecraftServer {
@@ -13,10 +15,90 @@ pub struct MinecraftServer {
     pub port: i16,
     pub whitelist: bool,
     pub online: Online,
-    pub historic_players: HashSet<HistoricPlayer>,
     pub motd: String,
     pub version: Version,
     pub last_updated: DateTime,
+    #[serde(default)]
+    pub forge: bool,
+    #[serde(default)]
+    pub mods: Vec<ServerMod>,
+    #[serde(default)]
+    pub mods_truncated: bool,
+    #[serde(default)]
+    pub forge_channels: Vec<ForgeChannel>,
+    #[serde(default)]
+    pub parse_warnings: Vec<String>,
+    #[serde(default)]
+    pub enforces_secure_chat: bool,
+    #[serde(default)]
+    pub previews_chat: Option<bool>,
+    #[serde(default)]
+    pub prevents_chat_reports: Option<bool>,
+    #[serde(default)]
+    pub is_modded: Option<bool>,
+    #[serde(default)]
+    pub mod_info_type: Option<String>,
+    #[serde(default)]
+    pub extensions: Vec<StatusExtension>,
+    #[serde(default)]
+    pub software: Fingerprint,
+    #[serde(default)]
+    pub resolved_version: ResolvedVersion,
+    /// Class of the most recent failed probe, kept after the server recovers
+    #[serde(default)]
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub last_error_message: Option<String>,
+    #[serde(default)]
+    pub last_error_at: Option<DateTime>,
+    /// Failed probes since the last successful one
+    #[serde(default)]
+    pub consecutive_failures: i32,
+    #[serde(default)]
+    pub status: ServerStatus,
+    /// When `status` last changed
+    #[serde(default)]
+    pub status_since: Option<DateTime>,
+    /// When `watch` will probe the server next
+    #[serde(default)]
+    pub next_probe_at: Option<DateTime>,
+}
+
+/// What the last probes of a server found.
+///
+/// Starts out `Online` when the server first answers, any failed probe moves
+/// it to `Unreachable` or `NotMinecraft` and enough of them in a row to
+/// `Gone`. The next successful probe brings it back to `Online`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerStatus {
+    #[default]
+    Online,
+    Unreachable,
+    /// Something else took over the port
+    NotMinecraft,
+    Gone,
+}
+
+impl ServerStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerStatus::Online => "online",
+            ServerStatus::Unreachable => "unreachable",
+            ServerStatus::NotMinecraft => "not_minecraft",
+            ServerStatus::Gone => "gone",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "online" => Some(ServerStatus::Online),
+            "unreachable" => Some(ServerStatus::Unreachable),
+            "not_minecraft" => Some(ServerStatus::NotMinecraft),
+            "gone" => Some(ServerStatus::Gone),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -25,3 +107,11 @@ pub struct Online {
     pub players: i32,
     pub list: HashSet<OnlinePlayer>,
 }
+
+/// Unrecognised top level status key, value kept as JSON text since keys and
+/// values can be anything a plugin decides to send.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StatusExtension {
+    pub key: String,
+    pub value: String,
+}
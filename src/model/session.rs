@@ -0,0 +1,177 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::{observation::ServerObservation, player::OnlinePlayer, uuid::UUID};
+
+/// Stretch of time a player was seen on a server. A session stays open, with
+/// `ended_at` unset, for as long as the player keeps showing up in the sample.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerSession {
+    pub uuid: UUID,
+    pub host: String,
+    pub port: i16,
+    pub started_at: DateTime,
+    pub last_seen: DateTime,
+    /// Last time the player was seen before the session was closed
+    pub ended_at: Option<DateTime>,
+}
+
+impl PlayerSession {
+    /// Time between the first and last sighting, so a lower bound on how long
+    /// the player was actually online.
+    pub fn duration(&self) -> Duration {
+        let end = self.ended_at.unwrap_or(self.last_seen);
+        Duration::from_millis(
+            (end.timestamp_millis() - self.started_at.timestamp_millis()).max(0) as u64,
+        )
+    }
+}
+
+/// Session of a player that hasn't been closed yet.
+#[derive(Debug, Clone, Copy)]
+pub struct OpenSession {
+    pub started_at: DateTime,
+    pub last_seen: DateTime,
+}
+
+/// How a server's open sessions change with a new observation.
+#[derive(Debug, Clone, Default)]
+pub struct SessionChanges {
+    /// Every player in the sample, `online_since` carried over from their
+    /// open session or set to the observation time for new ones
+    pub online: Vec<OnlinePlayer>,
+    /// Players with an open session who are no longer in the sample, or whose
+    /// session went longer than the gap without a sighting. These have to be
+    /// closed before the sessions in `online` are written.
+    pub ended: Vec<UUID>,
+}
+
+impl SessionChanges {
+    /// `open` holds the server's open sessions, keyed by uuid. A session last
+    /// seen more than `gap` before the observation is closed where it was
+    /// last seen, as the player could have come and gone in between, and a
+    /// player still in the sample starts a new one.
+    pub fn new(
+        open: &HashMap<UUID, OpenSession>,
+        observation: &ServerObservation,
+        gap: Duration,
+    ) -> Self {
+        let cutoff = observation.observed_at.timestamp_millis() - gap.as_millis() as i64;
+        let stale = |session: &OpenSession| session.last_seen.timestamp_millis() < cutoff;
+
+        let online: Vec<OnlinePlayer> = observation
+            .known_online()
+            .map(|player| OnlinePlayer {
+                uuid: player.uuid.clone(),
+                online_since: open
+                    .get(&player.uuid)
+                    .filter(|session| !stale(session))
+                    .map(|session| session.started_at)
+                    .unwrap_or(observation.observed_at),
+            })
+            .collect();
+
+        // Vanilla only samples up to 12 players, someone missing from a
+        // partial sample may well still be online
+        let complete = observation.online.list.len() as i64 >= observation.online.players as i64;
+        let present: HashSet<&UUID> = online.iter().map(|player| &player.uuid).collect();
+        let ended = open
+            .iter()
+            .filter(|(uuid, session)| stale(session) || (complete && !present.contains(uuid)))
+            .map(|(uuid, _)| uuid.clone())
+            .collect();
+
+        Self { online, ended }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::response::{Response, ResponseData};
+
+    use super::*;
+
+    const STEVE: &str = "8667ba71-b85a-4004-af54-457a9734eed7";
+    const ALEX: &str = "ec561538-f3fd-461d-aff5-086b22154bce";
+    const GAP: Duration = Duration::from_secs(1_200);
+
+    fn observation(observed_at: i64, sample: &[&str]) -> ServerObservation {
+        let sample: Vec<_> = sample
+            .iter()
+            .map(|uuid| json!({"name": "player", "id": uuid}))
+            .collect();
+        let status = json!({
+            "version": {"name": "1.20.1", "protocol": 763},
+            "players": {"max": 20, "online": sample.len(), "sample": sample},
+            "description": "test server",
+        });
+        let response = Response {
+            len: 0,
+            packet_id: 0,
+            data: ResponseData::from_value(&status),
+            raw: status,
+            login: None,
+            peer: None,
+        };
+
+        let mut observation = ServerObservation::from_response(&response, Duration::ZERO, false);
+        observation.observed_at = DateTime::from_millis(observed_at);
+        observation
+    }
+
+    fn open(uuid: &str, started_at: i64, last_seen: i64) -> HashMap<UUID, OpenSession> {
+        HashMap::from([(
+            UUID(uuid.to_owned()),
+            OpenSession {
+                started_at: DateTime::from_millis(started_at),
+                last_seen: DateTime::from_millis(last_seen),
+            },
+        )])
+    }
+
+    #[test]
+    fn carries_over_recent_sessions() {
+        let open = open(STEVE, 1_000, 60_000);
+        let changes = SessionChanges::new(&open, &observation(120_000, &[STEVE]), GAP);
+
+        assert_eq!(changes.online[0].online_since, DateTime::from_millis(1_000));
+        assert!(changes.ended.is_empty());
+    }
+
+    #[test]
+    fn closes_sessions_past_the_gap() {
+        let open = open(STEVE, 1_000, 60_000);
+        let changes = SessionChanges::new(&open, &observation(60_000 + 1_201_000, &[STEVE]), GAP);
+
+        assert_eq!(
+            changes.online[0].online_since,
+            DateTime::from_millis(1_261_000)
+        );
+        assert_eq!(changes.ended, vec![UUID(STEVE.to_owned())]);
+    }
+
+    #[test]
+    fn closes_sessions_of_players_gone_from_a_full_sample() {
+        let open = open(STEVE, 1_000, 60_000);
+        let changes = SessionChanges::new(&open, &observation(120_000, &[ALEX]), GAP);
+
+        assert_eq!(changes.ended, vec![UUID(STEVE.to_owned())]);
+    }
+
+    #[test]
+    fn leaves_out_anonymous_players() {
+        let anonymous = "00000000-0000-0000-0000-000000000000";
+        let observation = observation(120_000, &[anonymous, STEVE]);
+        let changes = SessionChanges::new(&HashMap::new(), &observation, GAP);
+
+        assert_eq!(changes.online.len(), 1);
+        assert_eq!(changes.online[0].uuid, UUID(STEVE.to_owned()));
+        let known: Vec<_> = observation.known_players().map(|p| &p.id).collect();
+        assert_eq!(known, vec![&UUID(STEVE.to_owned())]);
+    }
+}
@@ -1,4 +1,14 @@
 use serde::{Deserialize, Serialize};
 
+/// Servers that hide their player list fill the sample with this uuid.
+const ANONYMOUS: &str = "00000000-0000-0000-0000-000000000000";
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct UUID(pub String);
+
+impl UUID {
+    /// Whether this is the placeholder of a hidden sample rather than a player.
+    pub fn is_anonymous(&self) -> bool {
+        self.0 == ANONYMOUS
+    }
+}
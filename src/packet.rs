@@ -1,5 +1,3 @@
-use std::io::Write;
-
 use tokio::{io::AsyncReadExt, net::TcpStream};
 
 use crate::types::VarInt;
@@ -17,28 +15,28 @@ impl Packet {
         }
     }
 
-    pub fn writeVarInt(&mut self, int: i32) {
+    pub fn write_var_int(&mut self, int: i32) {
         self.bytes.append(&mut VarInt::new(int).bytes);
     }
 
-    pub fn writeString(&mut self, str: &str) {
-        self.writeVarInt(str.len() as i32);
+    pub fn write_string(&mut self, str: &str) {
+        self.write_var_int(str.len() as i32);
         self.bytes.append(&mut str.as_bytes().to_vec());
     }
 
-    pub fn writeShort(&mut self, short: i16) {
-        self.bytes.write(&short.to_be_bytes());
+    pub fn write_short(&mut self, short: i16) {
+        self.bytes.extend_from_slice(&short.to_be_bytes());
     }
 
-    pub fn writeByte(&mut self, byte: u8) {
+    pub fn write_byte(&mut self, byte: u8) {
         self.bytes.push(byte);
     }
 
-    pub fn writeBool(&mut self, b: bool) {
+    pub fn write_bool(&mut self, b: bool) {
         if b {
-            self.writeByte(0x01);
+            self.write_byte(0x01);
         } else {
-            self.writeByte(0x00);
+            self.write_byte(0x00);
         }
     }
 
@@ -66,36 +64,50 @@ impl Packet {
 }
 
 pub fn handshake_status_packet(ip: &str, port: i16) -> Packet {
-    handskake_packet(ip, port, 1)
+    handskake_packet(ip, port, -1, 1)
 }
 
-pub fn handshake_login_packet(ip: &str, port: i16) -> Packet {
-    handskake_packet(ip, port, 2)
+/// Unlike a status ping, a login is refused unless `protocol` is one the
+/// server accepts.
+pub fn handshake_login_packet(ip: &str, port: i16, protocol: i32) -> Packet {
+    handskake_packet(ip, port, protocol, 2)
 }
 
-pub fn handskake_packet(ip: &str, port: i16, next_state: i32) -> Packet {
+pub fn handskake_packet(ip: &str, port: i16, protocol: i32, next_state: i32) -> Packet {
     let mut packet = Packet::new(0);
 
-    packet.writeVarInt(-1);
-    packet.writeString(ip);
-    packet.writeShort(port);
-    packet.writeVarInt(next_state);
+    packet.write_var_int(protocol);
+    packet.write_string(ip);
+    packet.write_short(port);
+    packet.write_var_int(next_state);
 
     packet
 }
 
 pub fn status_request_packet() -> Packet {
-    let mut packet = Packet::new(0);
-
-    packet
+    Packet::new(0)
 }
 
-pub fn login_start(username: &str, uuid: &str) -> Packet {
+/// Login Start as `protocol` expects it, the fields after the name changed
+/// with 1.19, 1.19.1, 1.19.3 and 1.20.2.
+pub fn login_start(username: &str, uuid: &[u8; 16], protocol: i32) -> Packet {
     let mut packet = Packet::new(0);
 
-    packet.writeString(username);
-    packet.writeBool(true);
-    packet.writeString(uuid);
+    packet.write_string(username);
+    match protocol {
+        ..=758 => {}
+        759 => packet.write_bool(false),
+        760 => {
+            packet.write_bool(false);
+            packet.write_bool(true);
+            packet.bytes.extend_from_slice(uuid);
+        }
+        761..=763 => {
+            packet.write_bool(true);
+            packet.bytes.extend_from_slice(uuid);
+        }
+        _ => packet.bytes.extend_from_slice(uuid),
+    }
 
     packet
 }
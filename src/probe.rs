@@ -0,0 +1,271 @@
+//! Status ping of a single server, with every way it can go wrong sorted
+//! into a `FailureKind`.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::time::{timeout, Instant};
+use tracing::{debug, debug_span, field, trace, Instrument};
+
+use crate::metrics::metrics;
+use crate::packet::{handshake_status_packet, status_request_packet};
+use crate::response::Response;
+
+/// Part of the ping a timeout happened in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Connect,
+    Handshake,
+    Status,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Connect => "connect",
+            Phase::Handshake => "handshake",
+            Phase::Status => "status",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    Refused,
+    Timeout(Phase),
+    Reset,
+    /// Connection closed before a full response came in
+    Closed,
+    /// Something answered, but not with a status packet
+    NotMinecraft,
+    MalformedJson,
+    Oversize,
+    /// Pre-1.7 server that only speaks the legacy ping
+    LegacyOnly,
+    Other,
+}
+
+impl FailureKind {
+    /// Name the failure is stored and reported under.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureKind::Refused => "refused",
+            FailureKind::Timeout(Phase::Connect) => "timeout_connect",
+            FailureKind::Timeout(Phase::Handshake) => "timeout_handshake",
+            FailureKind::Timeout(Phase::Status) => "timeout_status",
+            FailureKind::Reset => "reset",
+            FailureKind::Closed => "closed",
+            FailureKind::NotMinecraft => "not_minecraft",
+            FailureKind::MalformedJson => "malformed_json",
+            FailureKind::Oversize => "oversize",
+            FailureKind::LegacyOnly => "legacy_only",
+            FailureKind::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ProbeError {
+    pub kind: FailureKind,
+    pub message: String,
+}
+
+impl ProbeError {
+    pub fn new(kind: FailureKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    fn timeout(phase: Phase) -> Self {
+        Self::new(FailureKind::Timeout(phase), "timed out")
+    }
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.as_str(), self.message)
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+/// Sorts an I/O error by kind. A timeout from the OS can't tell which phase
+/// it happened in, `run_phase` fills that in.
+impl From<io::Error> for ProbeError {
+    fn from(value: io::Error) -> Self {
+        let kind = match value.kind() {
+            io::ErrorKind::ConnectionRefused => FailureKind::Refused,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => FailureKind::Reset,
+            io::ErrorKind::UnexpectedEof => FailureKind::Closed,
+            io::ErrorKind::TimedOut => FailureKind::Timeout(Phase::Connect),
+            _ => FailureKind::Other,
+        };
+        Self::new(kind, value.to_string())
+    }
+}
+
+/// Connects, sends the handshake and status request and reads the response,
+/// allowing up to `phase_timeout` for each of the three. Runs in a `probe`
+/// span that records the phase reached, the outcome and how long it took.
+pub async fn probe(ip: &str, port: i16, phase_timeout: Duration) -> Result<Response, ProbeError> {
+    probe_via(format!("{}:{}", ip, port), ip, port, phase_timeout).await
+}
+
+/// Probes `host` at an address it was already resolved to, so the connection
+/// goes where the caller looked instead of wherever a new lookup points.
+pub async fn probe_address(
+    address: SocketAddr,
+    host: &str,
+    port: i16,
+    phase_timeout: Duration,
+) -> Result<Response, ProbeError> {
+    probe_via(address, host, port, phase_timeout).await
+}
+
+async fn probe_via(
+    address: impl ToSocketAddrs,
+    ip: &str,
+    port: i16,
+    phase_timeout: Duration,
+) -> Result<Response, ProbeError> {
+    let span = debug_span!(
+        "probe",
+        host = ip,
+        port,
+        phase = field::Empty,
+        outcome = field::Empty,
+        duration_ms = field::Empty,
+    );
+    let start = Instant::now();
+    let mut phase = Phase::Connect;
+    metrics().probes_in_flight.inc();
+    let result = exchange(address, ip, port, phase_timeout, &mut phase)
+        .instrument(span.clone())
+        .await;
+    metrics().probes_in_flight.dec();
+
+    span.record("phase", phase.as_str());
+    span.record("duration_ms", start.elapsed().as_millis() as u64);
+    let _entered = span.enter();
+    let outcome = match &result {
+        Ok(_) => {
+            span.record("outcome", "online");
+            debug!("probe finished");
+            "online"
+        }
+        Err(err) => {
+            span.record("outcome", err.kind.as_str());
+            debug!(error = %err.message, "probe failed");
+            err.kind.as_str()
+        }
+    };
+    metrics().probes.with_label_values(&[outcome]).inc();
+
+    result
+}
+
+/// Runs one phase of the probe within `phase_timeout`, keeping `current` at
+/// it and recording how long it took.
+async fn run_phase<T, E>(
+    current: &mut Phase,
+    phase: Phase,
+    phase_timeout: Duration,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, ProbeError>
+where
+    ProbeError: From<E>,
+{
+    *current = phase;
+    trace!(phase = phase.as_str(), "entering phase");
+    let start = Instant::now();
+    let result = timeout(phase_timeout, future).await;
+    metrics()
+        .phase_duration
+        .with_label_values(&[phase.as_str()])
+        .observe(start.elapsed().as_secs_f64());
+
+    match result {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(err)) => {
+            let mut err = ProbeError::from(err);
+            if let FailureKind::Timeout(_) = err.kind {
+                err.kind = FailureKind::Timeout(phase);
+            }
+            Err(err)
+        }
+        Err(_) => Err(ProbeError::timeout(phase)),
+    }
+}
+
+/// Does the actual probe, keeping `phase` at the phase it's in.
+async fn exchange(
+    address: impl ToSocketAddrs,
+    ip: &str,
+    port: i16,
+    phase_timeout: Duration,
+    phase: &mut Phase,
+) -> Result<Response, ProbeError> {
+    let mut stream = run_phase(
+        phase,
+        Phase::Connect,
+        phase_timeout,
+        TcpStream::connect(address),
+    )
+    .await?;
+
+    let handshake_packet = handshake_status_packet(ip, port);
+    let status_request_packet = status_request_packet();
+
+    run_phase(phase, Phase::Handshake, phase_timeout, async {
+        stream.write_all(&handshake_packet.to_bytes()).await?;
+        stream.write_all(&status_request_packet.to_bytes()).await?;
+        stream.flush().await
+    })
+    .await?;
+
+    let mut response = run_phase(
+        phase,
+        Phase::Status,
+        phase_timeout,
+        Response::read(&mut stream),
+    )
+    .await?;
+    response.data.host = ip.to_owned();
+    response.data.port = port;
+    trace!(
+        protocol = response.data.version.protocol,
+        "read status response"
+    );
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn os_timeouts_take_the_phase() {
+        let mut current = Phase::Connect;
+        let timed_out = async { Err::<(), _>(io::Error::from(io::ErrorKind::TimedOut)) };
+
+        let err = run_phase(
+            &mut current,
+            Phase::Status,
+            Duration::from_secs(1),
+            timed_out,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind, FailureKind::Timeout(Phase::Status));
+    }
+}
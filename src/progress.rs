@@ -0,0 +1,279 @@
+//! Progress of a scan, as a live bar on a terminal or as periodic plain lines
+//! otherwise, and the summary printed when it ends.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use kdam::term::Colorizer;
+use kdam::{tqdm, BarExt, Column, RichProgress};
+use tokio::time::Instant;
+
+use crate::probe::FailureKind;
+use crate::ratelimit::RateLimiter;
+
+/// How often the bar is redrawn.
+const BAR_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Versions listed in the summary.
+const SUMMARY_VERSIONS: usize = 10;
+
+/// Failure classes shown next to the bar.
+const BAR_FAILURE_CLASSES: usize = 3;
+
+/// How a finished probe went.
+pub enum Outcome {
+    Online {
+        host: String,
+        port: i16,
+        protocol: i32,
+        /// Newest release speaking the protocol, or the advertised name
+        version: String,
+    },
+    Failed(FailureKind),
+}
+
+#[derive(Debug, Default)]
+pub struct ScanStats {
+    pub started: usize,
+    pub online: usize,
+    /// Failed probes per `FailureKind::as_str`
+    pub failed: BTreeMap<&'static str, usize>,
+    /// Online servers per protocol, with the version shown for it
+    pub versions: HashMap<i32, (String, usize)>,
+    /// Online servers storage didn't know about before the scan, `None` if
+    /// it couldn't be asked
+    pub new_servers: Option<usize>,
+}
+
+impl ScanStats {
+    pub fn failed(&self) -> usize {
+        self.failed.values().sum()
+    }
+
+    pub fn finished(&self) -> usize {
+        self.online + self.failed()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.started - self.finished()
+    }
+
+    /// Counts a finished probe, `new` tells whether an online server is one
+    /// storage didn't know about.
+    pub fn record(&mut self, outcome: &Outcome, new: Option<bool>) {
+        match outcome {
+            Outcome::Online {
+                protocol, version, ..
+            } => {
+                self.online += 1;
+                self.versions
+                    .entry(*protocol)
+                    .or_insert_with(|| (version.clone(), 0))
+                    .1 += 1;
+                if let (Some(count), Some(new)) = (&mut self.new_servers, new) {
+                    *count += new as usize;
+                }
+            }
+            Outcome::Failed(kind) => *self.failed.entry(kind.as_str()).or_default() += 1,
+        }
+    }
+
+    /// Failure classes, most common first.
+    fn failure_classes(&self) -> Vec<(&'static str, usize)> {
+        let mut classes = self
+            .failed
+            .iter()
+            .map(|(class, count)| (*class, *count))
+            .collect::<Vec<_>>();
+        classes.sort_by_key(|(_, count)| Reverse(*count));
+        classes
+    }
+}
+
+pub struct Progress {
+    /// `None` when printing plain lines
+    bar: Option<RichProgress>,
+    total: usize,
+    limiter: Arc<RateLimiter>,
+    interval: Duration,
+    start: Instant,
+    last: Instant,
+    started_before: usize,
+}
+
+impl Progress {
+    /// Draws a bar unless `plain`, in which case a line of stats is printed
+    /// every `stats_interval`.
+    pub fn new(plain: bool, stats_interval: Duration, limiter: Arc<RateLimiter>) -> Self {
+        let bar = (!plain).then(|| {
+            RichProgress::new(
+                tqdm!(unit = "ips"),
+                vec![
+                    Column::Spinner(
+                        "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏"
+                            .chars()
+                            .map(|x| x.to_string())
+                            .collect::<Vec<String>>(),
+                        80.0,
+                        1.0,
+                    ),
+                    Column::text("[bold blue]starting"),
+                    Column::Bar,
+                    Column::Percentage(1),
+                    Column::text("•"),
+                    Column::CountTotal,
+                    Column::text("•"),
+                    Column::RemainingTime,
+                ],
+            )
+        });
+
+        Self {
+            bar,
+            total: 0,
+            limiter,
+            interval: if plain { stats_interval } else { BAR_INTERVAL },
+            start: Instant::now(),
+            last: Instant::now(),
+            started_before: 0,
+        }
+    }
+
+    pub fn set_total(&mut self, total: usize) {
+        self.total = total;
+        if let Some(pb) = &mut self.bar {
+            pb.pb.set_total(total);
+        }
+    }
+
+    pub fn message(&mut self, text: &str, colour: &str) {
+        match &mut self.bar {
+            Some(pb) => pb.write(text.colorize(colour)),
+            None => eprintln!("{}", text),
+        }
+    }
+
+    /// Redraws the bar or prints a line of stats if it's time to.
+    pub fn update(&mut self, stats: &ScanStats) {
+        if self.last.elapsed() < self.interval {
+            return;
+        }
+
+        let rate = (stats.started - self.started_before) as f64 / self.last.elapsed().as_secs_f64();
+        self.started_before = stats.started;
+        self.last = Instant::now();
+
+        let status = self.status(stats, rate);
+        match &mut self.bar {
+            Some(pb) => {
+                pb.replace(1, Column::text(&format!("[bold blue]{}", status)));
+                pb.update_to(stats.finished());
+            }
+            None => {
+                let percent = if self.total > 0 {
+                    stats.finished() as f64 * 100.0 / self.total as f64
+                } else {
+                    100.0
+                };
+                eprintln!(
+                    "{}/{} ips ({:.1}%), {}",
+                    stats.finished(),
+                    self.total,
+                    percent,
+                    status
+                );
+            }
+        }
+    }
+
+    /// Running, online and failed probes and the current probe rate.
+    fn status(&self, stats: &ScanStats, rate: f64) -> String {
+        let mut status = format!(
+            "{} running, {} online, {} failed",
+            stats.in_flight(),
+            stats.online,
+            stats.failed()
+        );
+
+        let classes = stats.failure_classes();
+        if !classes.is_empty() {
+            let shown = classes
+                .iter()
+                .take(BAR_FAILURE_CLASSES)
+                .map(|(class, count)| format!("{} {}", class, count))
+                .collect::<Vec<_>>();
+            status += &format!(" ({})", shown.join(", "));
+        }
+
+        status += &format!(", {:.0}/s", rate);
+        let limits = self.limiter.limits();
+        if limits.global.is_some() || limits.per_subnet.is_some() {
+            status += &format!(
+                " (limit {}, {} throttled)",
+                limits,
+                self.limiter.throttled()
+            );
+        }
+        status
+    }
+
+    /// Prints counts per outcome, the most common versions and how many of
+    /// the online servers are new.
+    pub fn summary(&mut self, stats: &ScanStats) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let status = self.status(stats, stats.started as f64 / elapsed.max(f64::EPSILON));
+        if let Some(pb) = &mut self.bar {
+            pb.replace(1, Column::text(&format!("[bold blue]{}", status)));
+            pb.update_to(stats.finished());
+        }
+
+        let mut lines = vec![format!(
+            "Probed {} of {} ips in {:.1}s, {:.1}/s",
+            stats.finished(),
+            self.total,
+            elapsed,
+            stats.finished() as f64 / elapsed.max(f64::EPSILON)
+        )];
+
+        lines.push(format!("{:<20}{:>10}", "outcome", "probes"));
+        lines.push(format!("{:<20}{:>10}", "online", stats.online));
+        for (class, count) in stats.failure_classes() {
+            lines.push(format!("{:<20}{:>10}", class, count));
+        }
+
+        if let Some(new) = stats.new_servers {
+            lines.push(format!("{} new servers, {} known", new, stats.online - new));
+        }
+
+        if !stats.versions.is_empty() {
+            let mut versions = stats.versions.iter().collect::<Vec<_>>();
+            versions.sort_by(|a, b| b.1 .1.cmp(&a.1 .1).then(b.0.cmp(a.0)));
+            lines.push(format!(
+                "{:<10}{:<30}{:>10}",
+                "protocol", "version", "servers"
+            ));
+            for (protocol, (version, count)) in versions.iter().take(SUMMARY_VERSIONS) {
+                lines.push(format!("{:<10}{:<30}{:>10}", protocol, version, count));
+            }
+            if versions.len() > SUMMARY_VERSIONS {
+                lines.push(format!(
+                    "and {} more versions",
+                    versions.len() - SUMMARY_VERSIONS
+                ));
+            }
+        }
+
+        if self.limiter.throttled() > 0 {
+            lines.push(format!(
+                "{} probes waited for the rate limit",
+                self.limiter.throttled()
+            ));
+        }
+
+        for line in lines {
+            self.message(&line, "bold green");
+        }
+    }
+}
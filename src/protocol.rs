@@ -0,0 +1,303 @@
+//! Protocol number to release name tables.
+//!
+//! Servers are free to put anything in `version.name`, the protocol number is
+//! what the client actually has to speak, so it's the better source of truth.
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::mods::compare_versions;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Edition {
+    #[serde(alias = "java")]
+    Java,
+    #[serde(alias = "bedrock")]
+    Bedrock,
+}
+
+/// Java release protocols since the netty rewrite in 1.7.2, older clients
+/// don't speak the status handshake probes use.
+const JAVA_PROTOCOLS: [(i32, &[&str]); 48] = [
+    (4, &["1.7.2", "1.7.3", "1.7.4", "1.7.5"]),
+    (5, &["1.7.6", "1.7.7", "1.7.8", "1.7.9", "1.7.10"]),
+    (
+        47,
+        &[
+            "1.8", "1.8.1", "1.8.2", "1.8.3", "1.8.4", "1.8.5", "1.8.6", "1.8.7", "1.8.8", "1.8.9",
+        ],
+    ),
+    (107, &["1.9"]),
+    (108, &["1.9.1"]),
+    (109, &["1.9.2"]),
+    (110, &["1.9.3", "1.9.4"]),
+    (210, &["1.10", "1.10.1", "1.10.2"]),
+    (315, &["1.11"]),
+    (316, &["1.11.1", "1.11.2"]),
+    (335, &["1.12"]),
+    (338, &["1.12.1"]),
+    (340, &["1.12.2"]),
+    (393, &["1.13"]),
+    (401, &["1.13.1"]),
+    (404, &["1.13.2"]),
+    (477, &["1.14"]),
+    (480, &["1.14.1"]),
+    (485, &["1.14.2"]),
+    (490, &["1.14.3"]),
+    (498, &["1.14.4"]),
+    (573, &["1.15"]),
+    (575, &["1.15.1"]),
+    (578, &["1.15.2"]),
+    (735, &["1.16"]),
+    (736, &["1.16.1"]),
+    (751, &["1.16.2"]),
+    (753, &["1.16.3"]),
+    (754, &["1.16.4", "1.16.5"]),
+    (755, &["1.17"]),
+    (756, &["1.17.1"]),
+    (757, &["1.18", "1.18.1"]),
+    (758, &["1.18.2"]),
+    (759, &["1.19"]),
+    (760, &["1.19.1", "1.19.2"]),
+    (761, &["1.19.3"]),
+    (762, &["1.19.4"]),
+    (763, &["1.20", "1.20.1"]),
+    (764, &["1.20.2"]),
+    (765, &["1.20.3", "1.20.4"]),
+    (766, &["1.20.5", "1.20.6"]),
+    (767, &["1.21", "1.21.1"]),
+    (768, &["1.21.2", "1.21.3"]),
+    (769, &["1.21.4"]),
+    (770, &["1.21.5"]),
+    (771, &["1.21.6"]),
+    (772, &["1.21.7", "1.21.8"]),
+    (773, &["1.21.9", "1.21.10"]),
+];
+
+const BEDROCK_PROTOCOLS: [(i32, &[&str]); 36] = [
+    (407, &["1.16.0"]),
+    (408, &["1.16.20"]),
+    (419, &["1.16.100"]),
+    (422, &["1.16.200"]),
+    (428, &["1.16.210"]),
+    (431, &["1.16.220"]),
+    (440, &["1.17.0"]),
+    (448, &["1.17.10"]),
+    (465, &["1.17.30"]),
+    (471, &["1.17.40"]),
+    (475, &["1.18.0"]),
+    (486, &["1.18.10"]),
+    (503, &["1.18.30"]),
+    (527, &["1.19.0"]),
+    (534, &["1.19.10"]),
+    (544, &["1.19.20"]),
+    (554, &["1.19.30"]),
+    (557, &["1.19.40"]),
+    (560, &["1.19.50"]),
+    (567, &["1.19.60"]),
+    (575, &["1.19.70"]),
+    (582, &["1.19.80"]),
+    (589, &["1.20.0"]),
+    (594, &["1.20.10"]),
+    (618, &["1.20.30"]),
+    (622, &["1.20.40"]),
+    (630, &["1.20.50"]),
+    (649, &["1.20.60"]),
+    (662, &["1.20.70"]),
+    (671, &["1.20.80"]),
+    (685, &["1.21.0"]),
+    (686, &["1.21.2"]),
+    (712, &["1.21.20"]),
+    (729, &["1.21.30"]),
+    (748, &["1.21.40"]),
+    (766, &["1.21.50"]),
+];
+
+/// Release names for a protocol number, empty if it isn't a known release.
+pub fn releases(edition: Edition, protocol: i32) -> &'static [&'static str] {
+    let table: &[(i32, &[&str])] = match edition {
+        Edition::Java => &JAVA_PROTOCOLS,
+        Edition::Bedrock => &BEDROCK_PROTOCOLS,
+    };
+
+    table
+        .iter()
+        .find(|(p, _)| *p == protocol)
+        .map(|(_, releases)| *releases)
+        .unwrap_or(&[])
+}
+
+/// Protocol number and advertised name reconciled against the release table.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResolvedVersion {
+    pub edition: Edition,
+    /// Releases that speak the reported protocol
+    pub releases: Vec<String>,
+    /// Version numbers found in the advertised name
+    pub advertised: Vec<String>,
+    /// The advertised name names versions but none of them match the protocol
+    pub mismatch: bool,
+}
+
+impl Default for ResolvedVersion {
+    fn default() -> Self {
+        Self {
+            edition: Edition::Java,
+            releases: Vec::new(),
+            advertised: Vec::new(),
+            mismatch: false,
+        }
+    }
+}
+
+pub fn resolve(edition: Edition, name: &str, protocol: i32) -> ResolvedVersion {
+    let releases: Vec<String> = releases(edition, protocol)
+        .iter()
+        .map(|r| r.to_string())
+        .collect();
+    let advertised = version_tokens(name);
+
+    let mismatch = !releases.is_empty()
+        && !advertised.is_empty()
+        && !releases
+            .iter()
+            .any(|release| advertised_covers(&advertised, name, release));
+
+    ResolvedVersion {
+        edition,
+        releases,
+        advertised,
+        mismatch,
+    }
+}
+
+/// Whether `release` is named in the advertised versions, either directly,
+/// as a `1.20.x` wildcard or inside a `1.8-1.20` style range.
+fn advertised_covers(advertised: &[String], name: &str, release: &str) -> bool {
+    let matches = |token: &String| {
+        let token = token.trim_end_matches(".x");
+        release == token || release.starts_with(&format!("{}.", token))
+    };
+    if advertised.iter().any(matches) {
+        return true;
+    }
+
+    if advertised.len() >= 2 && name.contains('-') {
+        let min = advertised.first().unwrap().trim_end_matches(".x");
+        let max = advertised.last().unwrap().trim_end_matches(".x");
+        return compare_versions(release, min) != Ordering::Less
+            && (compare_versions(release, max) != Ordering::Greater
+                || release.starts_with(&format!("{}.", max)));
+    }
+
+    false
+}
+
+/// Pulls version numbers like `1.20.4` or `1.8.x` out of free text.
+fn version_tokens(name: &str) -> Vec<String> {
+    name.split(|c: char| !(c.is_ascii_digit() || c == '.' || c == 'x'))
+        .map(|token| token.trim_matches('.'))
+        .filter(|token| {
+            let mut parts = token.split('.');
+            parts.next() == Some("1")
+                && parts.clone().count() >= 1
+                && parts
+                    .all(|p| p == "x" || (!p.is_empty() && p.chars().all(|c| c.is_ascii_digit())))
+        })
+        .map(|token| token.to_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_advertised_names_against_the_protocol() {
+        // (edition, name, protocol, releases, mismatch)
+        let cases: [(Edition, &str, i32, &[&str], bool); 12] = [
+            (Edition::Java, "1.20.1", 763, &["1.20", "1.20.1"], false),
+            (
+                Edition::Java,
+                "Paper 1.20.1",
+                763,
+                &["1.20", "1.20.1"],
+                false,
+            ),
+            (Edition::Java, "1.20.x", 765, &["1.20.3", "1.20.4"], false),
+            (
+                Edition::Java,
+                "Requires 1.8-1.20",
+                763,
+                &["1.20", "1.20.1"],
+                false,
+            ),
+            (Edition::Java, "1.8.x-1.12.2", 340, &["1.12.2"], false),
+            (
+                Edition::Java,
+                "Velocity 3.3.0",
+                763,
+                &["1.20", "1.20.1"],
+                false,
+            ),
+            (Edition::Java, "1.19.4", 763, &["1.20", "1.20.1"], true),
+            (
+                Edition::Java,
+                "Requires 1.8-1.12",
+                763,
+                &["1.20", "1.20.1"],
+                true,
+            ),
+            (Edition::Java, "1.20.1", 9999, &[], false),
+            (Edition::Java, "1.20.1", -1, &[], false),
+            (Edition::Bedrock, "1.21.50", 766, &["1.21.50"], false),
+            (Edition::Bedrock, "1.20.80", 766, &["1.21.50"], true),
+        ];
+
+        for (edition, name, protocol, releases, mismatch) in cases {
+            let resolved = resolve(edition, name, protocol);
+            assert_eq!(resolved.releases, releases, "{} ({})", name, protocol);
+            assert_eq!(resolved.mismatch, mismatch, "{} ({})", name, protocol);
+            assert_eq!(resolved.edition, edition);
+        }
+    }
+
+    #[test]
+    fn covers_releases_named_exactly_by_wildcard_or_by_range() {
+        // (name, release, covered)
+        let cases = [
+            ("1.20.1", "1.20.1", true),
+            ("1.20.1", "1.20", false),
+            ("1.20", "1.20.1", true),
+            ("1.20.x", "1.20.4", true),
+            ("1.8-1.20", "1.12.2", true),
+            ("1.8-1.20", "1.20.4", true),
+            ("1.8-1.20", "1.7.10", false),
+            ("1.8-1.20", "1.21", false),
+            ("1.8, 1.20", "1.12.2", false),
+        ];
+
+        for (name, release, covered) in cases {
+            let advertised = version_tokens(name);
+            assert_eq!(
+                advertised_covers(&advertised, name, release),
+                covered,
+                "{} covers {}",
+                name,
+                release
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_editions_apart() {
+        assert_eq!(
+            releases(Edition::Java, 4),
+            ["1.7.2", "1.7.3", "1.7.4", "1.7.5"]
+        );
+        assert!(releases(Edition::Bedrock, 4).is_empty());
+        assert_eq!(releases(Edition::Bedrock, 407), ["1.16.0"]);
+        assert!(releases(Edition::Java, 407).is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+//! Token buckets that cap how fast probes start, overall and per subnet, so a
+//! scan doesn't look like a flood to any single hosting provider.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Subnet buckets are pruned once this many were added since the last prune.
+const PRUNE_EVERY: usize = 4096;
+
+/// Prefix IPv6 addresses are grouped by, usually one customer.
+const IPV6_PREFIX: u8 = 48;
+
+/// Targets that may wait on the limiter for each probe allowed to run at once.
+/// Waiting doesn't hold a probe slot, so a throttled subnet can't take them
+/// all while targets elsewhere could go ahead.
+pub const QUEUED_PER_PROBE: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    /// Probes per second over all targets
+    pub global: Option<f64>,
+    /// Probes per second to any one subnet
+    pub per_subnet: Option<f64>,
+    /// Length of the IPv4 prefix that makes up a subnet
+    pub subnet_prefix: u8,
+}
+
+impl fmt::Display for RateLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.global, self.per_subnet) {
+            (None, None) => write!(f, "unlimited"),
+            (Some(global), None) => write!(f, "{}/s", global),
+            (None, Some(subnet)) => write!(f, "{}/s per /{}", subnet, self.subnet_prefix),
+            (Some(global), Some(subnet)) => {
+                write!(f, "{}/s, {}/s per /{}", global, subnet, self.subnet_prefix)
+            }
+        }
+    }
+}
+
+/// Holds up to `burst` tokens and gains `rate` of them every second.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Starts out full. Allows a second's worth of probes at once.
+    fn new(rate: f64, now: Instant) -> Self {
+        let burst = rate.max(1.0);
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+    }
+
+    /// Time until a token is available, zero if one is now.
+    fn wait_time(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::try_from_secs_f64((1.0 - self.tokens) / self.rate).unwrap_or(Duration::MAX)
+        }
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.burst
+    }
+}
+
+struct State {
+    global: Option<TokenBucket>,
+    subnets: HashMap<String, TokenBucket>,
+    added_since_prune: usize,
+}
+
+pub struct RateLimiter {
+    limits: RateLimits,
+    state: Mutex<State>,
+    throttled: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        let now = Instant::now();
+        Self {
+            limits,
+            state: Mutex::new(State {
+                global: limits
+                    .global
+                    .filter(|rate| *rate > 0.0)
+                    .map(|rate| TokenBucket::new(rate, now)),
+                subnets: HashMap::new(),
+                added_since_prune: 0,
+            }),
+            throttled: AtomicU64::new(0),
+        }
+    }
+
+    pub fn limits(&self) -> RateLimits {
+        self.limits
+    }
+
+    /// Probes that had to wait for a token.
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    /// Waits until both the global and the host's subnet bucket have a token
+    /// and takes one from each.
+    pub async fn acquire(&self, host: &str) {
+        let per_subnet = self.limits.per_subnet.filter(|rate| *rate > 0.0);
+        let subnet = per_subnet.map(|_| subnet_of(host, self.limits.subnet_prefix));
+        let mut waited = false;
+
+        loop {
+            let wait = self.try_take(subnet.as_deref(), per_subnet.unwrap_or_default());
+            if wait.is_zero() {
+                if waited {
+                    self.throttled.fetch_add(1, Ordering::Relaxed);
+                }
+                return;
+            }
+            waited = true;
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Takes a token from each bucket if all have one, otherwise returns how
+    /// long to wait before trying again.
+    fn try_take(&self, subnet: Option<&str>, subnet_rate: f64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let now = Instant::now();
+
+        let global_wait = state
+            .global
+            .as_mut()
+            .map(|bucket| bucket.wait_time(now))
+            .unwrap_or_default();
+        let subnet_bucket = subnet.map(|subnet| {
+            if !state.subnets.contains_key(subnet) {
+                state.added_since_prune += 1;
+            }
+            state
+                .subnets
+                .entry(subnet.to_owned())
+                .or_insert_with(|| TokenBucket::new(subnet_rate, now))
+        });
+        let subnet_wait = match subnet_bucket {
+            Some(bucket) => {
+                let wait = bucket.wait_time(now);
+                if wait.is_zero() && global_wait.is_zero() {
+                    bucket.tokens -= 1.0;
+                }
+                wait
+            }
+            None => Duration::ZERO,
+        };
+
+        let wait = global_wait.max(subnet_wait);
+        if wait.is_zero() {
+            if let Some(global) = &mut state.global {
+                global.tokens -= 1.0;
+            }
+        }
+
+        // Full buckets behave the same as new ones, no need to keep them
+        if state.added_since_prune >= PRUNE_EVERY {
+            state.subnets.retain(|_, bucket| !bucket.is_full(now));
+            state.added_since_prune = 0;
+        }
+
+        wait
+    }
+}
+
+/// Network address of the subnet `host` is in, or the host itself if it
+/// isn't an IP address.
+fn subnet_of(host: &str, ipv4_prefix: u8) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            let prefix = ipv4_prefix.min(32);
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            format!(
+                "{}/{}",
+                std::net::Ipv4Addr::from(u32::from(ip) & mask),
+                prefix
+            )
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let mask = u128::MAX << (128 - IPV6_PREFIX as u32);
+            format!(
+                "{}/{}",
+                std::net::Ipv6Addr::from(u128::from(ip) & mask),
+                IPV6_PREFIX
+            )
+        }
+        Err(_) => host.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_a_burst_then_waits_for_the_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(4.0, start);
+
+        for _ in 0..4 {
+            assert!(bucket.wait_time(start).is_zero());
+            bucket.tokens -= 1.0;
+        }
+        assert_eq!(bucket.wait_time(start), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn refills_up_to_the_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, start);
+        bucket.tokens = 0.0;
+
+        assert!(bucket
+            .wait_time(start + Duration::from_millis(500))
+            .is_zero());
+        assert!(!bucket.is_full(start + Duration::from_millis(500)));
+        assert!(bucket.is_full(start + Duration::from_secs(60)));
+        assert_eq!(bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn slow_rates_still_allow_one_probe_at_once() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(0.1, start);
+
+        assert!(bucket.wait_time(start).is_zero());
+        bucket.tokens -= 1.0;
+        assert_eq!(bucket.wait_time(start), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn waits_forever_on_vanishing_rates() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1e-320, start);
+        bucket.tokens = 0.0;
+
+        assert_eq!(bucket.wait_time(start), Duration::MAX);
+    }
+
+    #[test]
+    fn groups_hosts_by_subnet() {
+        let cases = [
+            ("203.0.113.77", 24, "203.0.113.0/24"),
+            ("203.0.113.77", 16, "203.0.0.0/16"),
+            ("203.0.113.77", 32, "203.0.113.77/32"),
+            ("203.0.113.77", 0, "0.0.0.0/0"),
+            ("2001:db8:1234:5678::1", 24, "2001:db8:1234::/48"),
+            ("2001:db8:1234:ffff:1::9", 8, "2001:db8:1234::/48"),
+            ("play.example.com", 24, "play.example.com"),
+        ];
+
+        for (host, prefix, subnet) in cases {
+            assert_eq!(subnet_of(host, prefix), subnet, "{}/{}", host, prefix);
+        }
+    }
+
+    #[test]
+    fn throttles_each_subnet_on_its_own() {
+        let limiter = RateLimiter::new(RateLimits {
+            global: None,
+            per_subnet: Some(1.0),
+            subnet_prefix: 24,
+        });
+        let subnet = |host| subnet_of(host, 24);
+
+        assert!(limiter
+            .try_take(Some(&subnet("198.51.100.1")), 1.0)
+            .is_zero());
+        assert!(!limiter
+            .try_take(Some(&subnet("198.51.100.2")), 1.0)
+            .is_zero());
+        assert!(limiter
+            .try_take(Some(&subnet("198.51.101.1")), 1.0)
+            .is_zero());
+    }
+}
@@ -1,83 +1,114 @@
-use std::{error::Error, fs::File, io::Write};
+use std::net::SocketAddr;
 
 use serde::{Deserialize, Serialize};
-use serde_json::Result;
+use serde_json::{Map, Value};
 use tokio::{io::AsyncReadExt, net::TcpStream};
 
-use crate::{model::uuid::UUID, packet, types::VarInt};
+use crate::{
+    forge::{decode_optimized, ForgeChannel, ForgeMods},
+    login::LoginBehaviour,
+    model::uuid::UUID,
+    probe::{FailureKind, ProbeError},
+    types::VarInt,
+};
 
 #[derive(Debug)]
 pub struct Response {
     pub len: i32,
     pub packet_id: i32,
     pub data: ResponseData,
+    /// Status JSON exactly as the server sent it
+    pub raw: Value,
+    /// Answer to a login, if one was attempted after the ping
+    pub login: Option<LoginBehaviour>,
+    /// Address the response came from, where a login probe goes too
+    pub peer: Option<SocketAddr>,
 }
 
+/// Largest status packet we'll read. Vanilla caps the JSON at 32767
+/// characters, this leaves room for modpacks that ignore that.
+pub const MAX_STATUS_LEN: usize = 2 * 1024 * 1024;
+
+/// First byte of the legacy kick packet pre-1.7 servers answer with.
+const LEGACY_KICK: u8 = 0xFF;
+
 impl Response {
-    pub async fn read(stream: &mut TcpStream) -> std::io::Result<Self> {
-        let mut pre_buf = [0u8; 5];
-        stream.read_exact(&mut pre_buf).await?;
+    pub async fn read(stream: &mut TcpStream) -> Result<Self, ProbeError> {
+        let len = read_length(stream).await?;
+        if len > MAX_STATUS_LEN {
+            return Err(ProbeError::new(
+                FailureKind::Oversize,
+                format!("{} byte status packet", len),
+            ));
+        }
 
-        let mut pre_buf_iter = pre_buf.iter();
-        let len = VarInt::parse(&mut pre_buf_iter) as usize;
-        let extra = pre_buf_iter.len();
+        if len == 0 {
+            return Err(ProbeError::new(FailureKind::NotMinecraft, "empty packet"));
+        }
 
-        let mut rest = vec![0; len - extra];
-        stream.read_exact(&mut rest).await?;
+        // Checked before reading the rest, whatever else answered likely
+        // sent less than its first byte claims and would just look closed
+        let packet_id = stream.read_u8().await? as i32;
+        if packet_id != 0 {
+            return Err(ProbeError::new(
+                FailureKind::NotMinecraft,
+                format!("unexpected packet id {}", packet_id),
+            ));
+        }
 
-        let mut buf = Vec::<u8>::new();
-        buf.append(&mut pre_buf_iter.cloned().collect::<Vec<u8>>());
-        buf.append(&mut rest);
+        let mut buf = vec![0; len - 1];
+        stream.read_exact(&mut buf).await?;
 
         let mut buf_iter = buf.iter();
-        let packet_id = VarInt::parse(&mut buf_iter);
         // The rest of the packet is a string so we need to read the length of it but can ignore it
-        VarInt::parse(&mut buf_iter);
+        VarInt::try_parse(&mut buf_iter)
+            .ok_or_else(|| ProbeError::new(FailureKind::NotMinecraft, "truncated status"))?;
         let data: Vec<u8> = buf_iter.cloned().collect();
 
-        /*
-        let data_str = std::string::String::from_utf8_lossy(&data);
-
-        let response_data_result: Result<ResponseData> = serde_json::from_str(&data_str);
-
-        let response_data = match response_data_result {
-            Ok(d) => d,
-            Err(error) => {
-                let err_file = File::create(stream.peer_addr().unwrap().ip().to_string());
-                err_file.unwrap().write_all(&data);
-                panic!("{}", error);
-            }
-        };
-         */
-
-        let response_data: ResponseData = serde_json::from_slice(data.as_slice())?;
-
-        /*
-        if stream
-            .peer_addr()
-            .unwrap()
-            .ip()
-            .to_string()
-            .eq("70.15.164.121")
-        {
-            let mut fout = File::options()
-                .append(true)
-                .create(true)
-                .open("out.raw")
-                .unwrap();
-            fout.write(data_str.as_bytes());
-            fout.write("\n".as_bytes());
-        }
-        */
+        let raw: Value = serde_json::from_slice(data.as_slice())
+            .map_err(|err| ProbeError::new(FailureKind::MalformedJson, err.to_string()))?;
+        let response_data = ResponseData::from_value(&raw);
 
         Ok(Self {
             len: len as i32,
             packet_id,
             data: response_data,
+            raw,
+            login: None,
+            peer: stream.peer_addr().ok(),
         })
     }
 }
 
+/// Reads the varint length prefix of a packet.
+///
+/// A legacy kick packet starts with `0xFF` followed by the high byte of a
+/// short string length, so nearly always `0x00`. No server encodes a length
+/// as `0xFF 0x00`, so that pair is taken to mean a pre-1.7 server.
+async fn read_length(stream: &mut TcpStream) -> Result<usize, ProbeError> {
+    let mut value = 0u32;
+
+    for position in 0..5 {
+        let byte = stream.read_u8().await?;
+        if position == 1 && byte == 0 && value == (LEGACY_KICK & 0x7F) as u32 {
+            return Err(ProbeError::new(
+                FailureKind::LegacyOnly,
+                "legacy kick packet",
+            ));
+        }
+
+        value |= (byte as u32 & 0x7F) << (7 * position);
+        if byte & 0x80 == 0 {
+            return Ok(value as usize);
+        }
+    }
+
+    Err(ProbeError::new(
+        FailureKind::NotMinecraft,
+        "length prefix too long",
+    ))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResponseData {
     pub version: Version,
@@ -93,6 +124,184 @@ pub struct ResponseData {
     pub port: i16,
     #[serde(alias = "forgeData")]
     pub forge_data: Option<ForgeData>,
+    #[serde(default)]
+    #[serde(alias = "previewsChat")]
+    pub previews_chat: Option<bool>,
+    /// Set by the NoChatReports mod
+    #[serde(default)]
+    #[serde(alias = "preventsChatReports")]
+    pub prevents_chat_reports: Option<bool>,
+    /// Set by NeoForge
+    #[serde(default)]
+    #[serde(alias = "isModded")]
+    pub is_modded: Option<bool>,
+    /// `modinfo.type`, usually `FML` or `BUKKIT`
+    #[serde(default)]
+    pub mod_info_type: Option<String>,
+    /// Top level keys we don't model
+    #[serde(default)]
+    pub extensions: Map<String, Value>,
+    /// Fields that were missing or malformed and had to be defaulted
+    #[serde(skip)]
+    pub parse_warnings: Vec<String>,
+}
+
+/// Top level status keys that are parsed into `ResponseData` fields.
+const KNOWN_KEYS: [&str; 10] = [
+    "version",
+    "players",
+    "favicon",
+    "description",
+    "enforcesSecureChat",
+    "previewsChat",
+    "preventsChatReports",
+    "isModded",
+    "forgeData",
+    "modinfo",
+];
+
+impl ResponseData {
+    /// Extracts every field that is valid, recording a warning for each one that
+    /// isn't instead of rejecting the whole response.
+    pub fn from_value(value: &Value) -> Self {
+        let mut warnings = Vec::new();
+        let empty = Map::new();
+        let root = match value.as_object() {
+            Some(root) => root,
+            None => {
+                warnings.push("status is not a JSON object".to_owned());
+                &empty
+            }
+        };
+
+        let version = match root.get("version") {
+            Some(version) => Version::from_value(version, &mut warnings),
+            None => {
+                warnings.push("version missing".to_owned());
+                Version::unknown()
+            }
+        };
+
+        let players = match root.get("players") {
+            Some(players) => Players::from_value(players, &mut warnings),
+            None => {
+                warnings.push("players missing".to_owned());
+                Players::empty()
+            }
+        };
+
+        let favicon = match root.get("favicon") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(favicon)) => Some(favicon.clone()),
+            Some(_) => {
+                warnings.push("favicon is not a string".to_owned());
+                None
+            }
+        };
+
+        let enforces_secure_chat =
+            lenient_bool(root, "enforcesSecureChat", &mut warnings).unwrap_or(false);
+        let previews_chat = lenient_bool(root, "previewsChat", &mut warnings);
+        let prevents_chat_reports = lenient_bool(root, "preventsChatReports", &mut warnings);
+        let is_modded = lenient_bool(root, "isModded", &mut warnings);
+
+        let mod_info_type = root
+            .get("modinfo")
+            .and_then(|mod_info| mod_info.get("type"))
+            .and_then(Value::as_str)
+            .map(|s| s.to_owned());
+
+        let extensions = root
+            .iter()
+            .filter(|(key, _)| !KNOWN_KEYS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        let description = match root.get("description") {
+            Some(description) => Description::Raw(chat_text(description)),
+            None => {
+                warnings.push("description missing".to_owned());
+                Description::Raw(default_string())
+            }
+        };
+
+        let forge_data = root
+            .get("forgeData")
+            .or_else(|| root.get("modinfo"))
+            .and_then(|forge_data| match ForgeData::deserialize(forge_data) {
+                Ok(ForgeData::Unknown(_)) => {
+                    warnings.push("forge data in unknown format".to_owned());
+                    Some(ForgeData::Unknown(forge_data.clone()))
+                }
+                Ok(forge_data) => Some(forge_data),
+                Err(err) => {
+                    warnings.push(format!("forge data: {}", err));
+                    None
+                }
+            });
+
+        Self {
+            version,
+            players,
+            favicon,
+            enforces_secure_chat,
+            description,
+            host: default_string(),
+            port: default_short(),
+            forge_data,
+            previews_chat,
+            prevents_chat_reports,
+            is_modded,
+            mod_info_type,
+            extensions,
+            parse_warnings: warnings,
+        }
+    }
+}
+
+/// Flattens a chat component (string, object with `extra`, or array) to plain text.
+fn chat_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(parts) => parts.iter().map(chat_text).collect(),
+        Value::Object(component) => {
+            let mut text = component
+                .get("text")
+                .or_else(|| component.get("translate"))
+                .map(chat_text)
+                .unwrap_or_default();
+            if let Some(extra) = component.get("extra") {
+                text.push_str(&chat_text(extra));
+            }
+            text
+        }
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => default_string(),
+    }
+}
+
+fn lenient_bool(root: &Map<String, Value>, key: &str, warnings: &mut Vec<String>) -> Option<bool> {
+    match root.get(key)? {
+        Value::Null => None,
+        Value::Bool(b) => Some(*b),
+        _ => {
+            warnings.push(format!("{} is not a bool", key));
+            None
+        }
+    }
+}
+
+/// Reads a count that may come as a float or a string, out of range values are
+/// clamped instead of wrapping around.
+fn lenient_i32(value: Option<&Value>) -> Option<i32> {
+    let n = match value? {
+        // `as` saturates for floats, including u64s too large for an i64
+        Value::Number(n) => n.as_i64().or_else(|| n.as_f64().map(|f| f as i64))?,
+        Value::String(s) => s.trim().parse::<i64>().ok()?,
+        _ => return None,
+    };
+    Some(i32::try_from(n).unwrap_or(if n < 0 { i32::MIN } else { i32::MAX }))
 }
 
 fn default_bool_false() -> bool {
@@ -113,6 +322,37 @@ pub struct Version {
     pub protocol: i32,
 }
 
+impl Version {
+    pub fn unknown() -> Self {
+        Self {
+            name: default_string(),
+            protocol: -1,
+        }
+    }
+
+    fn from_value(value: &Value, warnings: &mut Vec<String>) -> Self {
+        let name = match value.get("name") {
+            Some(name) => chat_text(name),
+            None => {
+                warnings.push("version.name missing".to_owned());
+                default_string()
+            }
+        };
+        let protocol = match lenient_i32(value.get("protocol")) {
+            Some(protocol) => protocol,
+            None => {
+                warnings.push("version.protocol missing or not a number".to_owned());
+                -1
+            }
+        };
+        if value.get("protocol").is_some_and(Value::is_string) {
+            warnings.push("version.protocol is a string".to_owned());
+        }
+
+        Self { name, protocol }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Players {
     pub max: i32,
@@ -123,6 +363,43 @@ pub struct Players {
     pub list: Vec<Player>,
 }
 
+impl Players {
+    pub fn empty() -> Self {
+        Self {
+            max: 0,
+            online: 0,
+            list: default_list(),
+        }
+    }
+
+    fn from_value(value: &Value, warnings: &mut Vec<String>) -> Self {
+        let max = lenient_i32(value.get("max")).unwrap_or_else(|| {
+            warnings.push("players.max missing or not a number".to_owned());
+            0
+        });
+        let online = lenient_i32(value.get("online")).unwrap_or_else(|| {
+            warnings.push("players.online missing or not a number".to_owned());
+            0
+        });
+
+        let mut list = default_list();
+        match value.get("sample").or_else(|| value.get("list")) {
+            None | Some(Value::Null) => {}
+            Some(Value::Array(sample)) => {
+                for player in sample {
+                    match Player::deserialize(player) {
+                        Ok(player) => list.push(player),
+                        Err(err) => warnings.push(format!("players.sample entry: {}", err)),
+                    }
+                }
+            }
+            Some(_) => warnings.push("players.sample is not an array".to_owned()),
+        }
+
+        Self { max, online, list }
+    }
+}
+
 fn default_list() -> Vec<Player> {
     Vec::with_capacity(0)
 }
@@ -145,31 +422,66 @@ pub enum Description {
 }
 
 impl Description {
-    pub fn text(self) -> String {
+    pub fn text(&self) -> String {
         match self {
-            Description::Raw(s) => s,
-            Description::Nested { text } => text,
+            Description::Raw(s) => s.clone(),
+            Description::Nested { text } => text.clone(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum ForgeData {
     ForgeData {
+        #[serde(default)]
         mods: Vec<Mod>,
+        #[serde(default)]
+        channels: Vec<ForgeChannel>,
         #[serde(alias = "fmlNetworkVersion")]
         fml_network_version: i32,
         #[serde(default = "default_bool_false")]
         truncated: bool,
+        /// FML3 compressed mod and channel list
+        #[serde(default)]
+        d: Option<String>,
     },
     ModInfo {
         #[serde(alias = "modList")]
         mod_list: Vec<Mod>,
     },
+    /// Anything we don't understand, kept so it never fails the whole response
+    Unknown(serde_json::Value),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl ForgeData {
+    pub fn resolve(&self) -> ForgeMods {
+        match self {
+            ForgeData::ForgeData {
+                mods,
+                channels,
+                truncated,
+                d,
+                ..
+            } => match d.as_deref().and_then(decode_optimized) {
+                Some(decoded) => decoded,
+                None => ForgeMods {
+                    truncated: *truncated,
+                    mods: mods.clone(),
+                    channels: channels.clone(),
+                },
+            },
+            ForgeData::ModInfo { mod_list } => ForgeMods {
+                truncated: false,
+                mods: mod_list.clone(),
+                channels: Vec::new(),
+            },
+            ForgeData::Unknown(_) => ForgeMods::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Mod {
     #[serde(alias = "modId")]
     #[serde(alias = "modid")]
@@ -177,5 +489,96 @@ pub struct Mod {
     #[serde(alias = "modMarker")]
     #[serde(alias = "modmarker")]
     #[serde(alias = "version")]
+    #[serde(default = "default_string")]
     pub mod_marker: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn status(players: Value, description: Value) -> Value {
+        json!({
+            "version": {"name": "1.20.1", "protocol": 763},
+            "players": players,
+            "description": description,
+        })
+    }
+
+    #[test]
+    fn flattens_every_description_shape() {
+        let cases = [
+            (json!("A Minecraft Server"), "A Minecraft Server"),
+            (
+                json!(["A ", {"text": "Minecraft"}, " Server"]),
+                "A Minecraft Server",
+            ),
+            (
+                json!({"text": "A ", "extra": [{"text": "Minecraft"}, " Server"]}),
+                "A Minecraft Server",
+            ),
+            (json!({"translate": "menu.server"}), "menu.server"),
+            (json!(null), ""),
+        ];
+
+        for (description, text) in cases {
+            let data =
+                ResponseData::from_value(&status(json!({"max": 20, "online": 1}), description));
+            assert_eq!(data.description.text(), text);
+            assert!(data.parse_warnings.is_empty(), "{:?}", data.parse_warnings);
+        }
+    }
+
+    #[test]
+    fn reads_counts_sent_as_strings_or_floats() {
+        let players = json!({"max": "20", "online": 3.0});
+        let data = ResponseData::from_value(&status(players, json!("")));
+
+        assert_eq!(data.players.max, 20);
+        assert_eq!(data.players.online, 3);
+        assert!(data.parse_warnings.is_empty(), "{:?}", data.parse_warnings);
+    }
+
+    #[test]
+    fn clamps_counts_out_of_range() {
+        let cases = [
+            (json!(4294967296u64), Some(i32::MAX)),
+            (json!(u64::MAX), Some(i32::MAX)),
+            (json!(-4294967296i64), Some(i32::MIN)),
+            (json!(1e20), Some(i32::MAX)),
+            (json!("4294967296"), Some(i32::MAX)),
+            (json!(20), Some(20)),
+            (json!("twenty"), None),
+            (json!(true), None),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(lenient_i32(Some(&value)), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn defaults_missing_players() {
+        let data = ResponseData::from_value(&json!({
+            "version": {"name": "1.20.1", "protocol": 763},
+            "description": "",
+        }));
+
+        assert_eq!(data.players.max, 0);
+        assert!(data.players.list.is_empty());
+        assert_eq!(data.parse_warnings, vec!["players missing"]);
+    }
+
+    #[test]
+    fn keeps_unknown_forge_data() {
+        let mut value = status(json!({"max": 20, "online": 0}), json!(""));
+        value["forgeData"] = json!({"something": "else"});
+        let data = ResponseData::from_value(&value);
+
+        assert!(matches!(data.forge_data, Some(ForgeData::Unknown(_))));
+        assert!(data.forge_data.unwrap().resolve().mods.is_empty());
+        assert_eq!(data.parse_warnings, vec!["forge data in unknown format"]);
+    }
+}
@@ -0,0 +1,24 @@
+//! Signals that ask a long running command to wind down.
+
+use tracing::error;
+
+/// Resolves on the first Ctrl-C or, on Unix, SIGTERM.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(err) => error!(error = %err, "error listening for SIGTERM"),
+        }
+    }
+
+    let _ = tokio::signal::ctrl_c().await;
+}
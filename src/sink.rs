@@ -0,0 +1,135 @@
+//! Writes every probe result as one JSON object per line, for piping scan
+//! results into other tools without going through a database.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use mongodb::bson::DateTime;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::task::{self, JoinHandle};
+
+use crate::probe::ProbeError;
+use crate::response::{Response, ResponseData};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// One line of output.
+#[derive(Debug, Serialize)]
+pub struct ProbeRecord<'a> {
+    pub target: String,
+    pub host: &'a str,
+    pub port: i16,
+    pub started_at: String,
+    pub duration_ms: u128,
+    pub outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<&'a ResponseData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<'a> ProbeRecord<'a> {
+    pub fn new(
+        host: &'a str,
+        port: i16,
+        started_at: DateTime,
+        duration: Duration,
+        result: &'a Result<Response, ProbeError>,
+    ) -> Self {
+        let (outcome, response, error_class, error) = match result {
+            Ok(response) => ("success", Some(&response.data), None, None),
+            Err(err) => (
+                "error",
+                None,
+                Some(err.kind.as_str()),
+                Some(err.message.clone()),
+            ),
+        };
+
+        Self {
+            target: format!("{}:{}", host, port),
+            host,
+            port,
+            started_at: started_at.try_to_rfc3339_string().unwrap_or_default(),
+            duration_ms: duration.as_millis(),
+            outcome,
+            response,
+            error_class,
+            error,
+        }
+    }
+}
+
+/// Lines queued for the writer before `write` waits for it to catch up.
+const QUEUE_LEN: usize = 1024;
+
+/// Serializes records on the caller and hands the lines to a blocking task,
+/// so slow disks or a full stdout pipe never stall the runtime's workers.
+pub struct JsonLinesSink {
+    sender: mpsc::Sender<Vec<u8>>,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl JsonLinesSink {
+    /// Opens `path` for writing, `-` meaning stdout.
+    pub fn open(path: &str, compression: Compression) -> io::Result<Self> {
+        let output: Box<dyn Write + Send> = if path == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(path)?)
+        };
+        let output = BufWriter::new(output);
+
+        let writer: Box<dyn Write + Send> = match compression {
+            Compression::None => Box::new(output),
+            Compression::Gzip => Box::new(GzEncoder::new(output, flate2::Compression::default())),
+            Compression::Zstd => Box::new(zstd::Encoder::new(output, 0)?.auto_finish()),
+        };
+
+        let (sender, receiver) = mpsc::channel(QUEUE_LEN);
+        let writer = task::spawn_blocking(move || write_lines(writer, receiver));
+
+        Ok(Self { sender, writer })
+    }
+
+    pub async fn write(&self, record: &ProbeRecord<'_>) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.sender
+            .send(line)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "output writer stopped"))
+    }
+
+    /// Writes what's still queued and closes the output, compressed streams
+    /// are only valid after this. Returns the error that stopped the writer,
+    /// if any.
+    pub async fn finish(self) -> io::Result<()> {
+        drop(self.sender);
+        self.writer.await?
+    }
+}
+
+fn write_lines(
+    mut writer: Box<dyn Write + Send>,
+    mut receiver: mpsc::Receiver<Vec<u8>>,
+) -> io::Result<()> {
+    while let Some(line) = receiver.blocking_recv() {
+        writer.write_all(&line)?;
+    }
+    writer.flush()?;
+    // Dropping finishes the gzip and zstd streams
+    drop(writer);
+    Ok(())
+}
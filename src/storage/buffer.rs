@@ -0,0 +1,320 @@
+use std::fmt;
+use std::slice;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Instant};
+use tracing::{error, warn};
+
+use super::{PendingWrite, Storage, StorageResult};
+use crate::metrics::metrics;
+
+/// Tries at a write before giving up on it, if the errors may pass.
+const WRITE_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, longer for each one after.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Write-behind buffer between the scan and the storage, so a scan costs a
+/// round trip per batch instead of one per response.
+///
+/// The queue is bounded, once the storage falls behind `push` waits and the
+/// scan slows down with it.
+pub struct WriteBuffer {
+    sender: mpsc::Sender<Message>,
+    worker: JoinHandle<()>,
+}
+
+enum Message {
+    Write(PendingWrite),
+    /// Flush now and report back how many writes were lost since the last
+    /// flush asked for
+    Flush(oneshot::Sender<usize>),
+}
+
+/// Why not everything pushed before a flush made it to the storage.
+#[derive(Debug)]
+pub enum FlushError {
+    /// Writes that failed to store, each logged as it failed
+    Lost(usize),
+    /// The worker is gone, so nothing is known about the writes
+    Stopped,
+}
+
+impl fmt::Display for FlushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlushError::Lost(writes) => write!(f, "{} writes failed to store", writes),
+            FlushError::Stopped => write!(f, "write buffer stopped"),
+        }
+    }
+}
+
+impl std::error::Error for FlushError {}
+
+impl WriteBuffer {
+    /// Flushes every `batch_size` writes, or every `interval` if fewer
+    /// came in by then.
+    pub fn new(storage: Arc<dyn Storage>, batch_size: usize, interval: Duration) -> Self {
+        let batch_size = batch_size.max(1);
+        let (sender, receiver) = mpsc::channel(batch_size);
+        let worker = tokio::spawn(flush_loop(storage, receiver, batch_size, interval));
+
+        Self { sender, worker }
+    }
+
+    pub async fn push(&self, write: PendingWrite) {
+        metrics().write_queue.inc();
+        // Only fails once the worker is gone, which can't happen before finish
+        let _ = self.sender.send(Message::Write(write)).await;
+    }
+
+    /// Waits until everything pushed before this call has been written, and
+    /// fails if any of it couldn't be, including writes flushed on their
+    /// own since the last call.
+    pub async fn flush(&self) -> Result<(), FlushError> {
+        let (done, flushed) = oneshot::channel();
+        self.sender
+            .send(Message::Flush(done))
+            .await
+            .map_err(|_| FlushError::Stopped)?;
+        match flushed.await {
+            Ok(0) => Ok(()),
+            Ok(lost) => Err(FlushError::Lost(lost)),
+            Err(_) => Err(FlushError::Stopped),
+        }
+    }
+
+    /// Flushes whatever is still buffered and waits for it to be written.
+    pub async fn finish(self) {
+        drop(self.sender);
+        if let Err(err) = self.worker.await {
+            error!(error = %err, "error flushing writes");
+        }
+    }
+}
+
+async fn flush_loop(
+    storage: Arc<dyn Storage>,
+    mut receiver: mpsc::Receiver<Message>,
+    batch_size: usize,
+    interval: Duration,
+) {
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut ticker = tokio::time::interval(interval);
+    // Since the last flush that was asked for
+    let mut lost = 0;
+
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Write(write)) => {
+                    batch.push(write);
+                    if batch.len() >= batch_size {
+                        lost += flush(storage.as_ref(), &mut batch).await;
+                    }
+                }
+                Some(Message::Flush(done)) => {
+                    lost += flush(storage.as_ref(), &mut batch).await;
+                    let _ = done.send(lost);
+                    lost = 0;
+                }
+                None => break,
+            },
+            _ = ticker.tick() => lost += flush(storage.as_ref(), &mut batch).await,
+        }
+    }
+
+    flush(storage.as_ref(), &mut batch).await;
+}
+
+/// Writes the batch in one go, and if that fails for anything but a lost
+/// connection, one write at a time so only the writes at fault are lost.
+/// Returns how many were.
+async fn flush(storage: &dyn Storage, batch: &mut Vec<PendingWrite>) -> usize {
+    if batch.is_empty() {
+        return 0;
+    }
+
+    let start = Instant::now();
+    let mut lost = 0;
+    match record_with_retries(storage, batch).await {
+        Ok(()) => {}
+        Err(err) if batch.len() == 1 || err.is_transient() => {
+            lost = batch.len();
+            error!(error = %err, writes = batch.len(), "error saving batch");
+        }
+        Err(err) => {
+            warn!(error = %err, writes = batch.len(), "error saving batch, saving writes one by one");
+            for write in batch.iter() {
+                if let Err(err) = record_with_retries(storage, slice::from_ref(write)).await {
+                    let (host, port) = write.server();
+                    lost += 1;
+                    error!(error = %err, host, port, "error saving write");
+                }
+            }
+        }
+    }
+    metrics().storage_write_errors.inc_by(lost as u64);
+    metrics()
+        .storage_write_duration
+        .observe(start.elapsed().as_secs_f64());
+    metrics().write_queue.sub(batch.len() as i64);
+    batch.clear();
+    lost
+}
+
+/// Tries the writes again while the storage fails in a way that may pass.
+async fn record_with_retries(storage: &dyn Storage, writes: &[PendingWrite]) -> StorageResult<()> {
+    let mut attempt = 1;
+    loop {
+        match storage.record_batch(writes).await {
+            Err(err) if err.is_transient() && attempt < WRITE_ATTEMPTS => {
+                warn!(error = %err, attempt, "error saving writes, retrying");
+                sleep(RETRY_DELAY * attempt).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use mongodb::bson::DateTime;
+
+    use crate::model::{
+        failure::ProbeFailure, mods::ModInstall, observation::ServerObservation,
+        session::PlayerSession,
+    };
+    use crate::storage::{
+        HistoryQuery, OnlineCount, PlayerCount, PlayerFilter, PlayerRow, ScheduledProbe,
+        ServerFilter, ServerRow, ServerState, StorageError, Uptime, VersionCount, WatchTarget,
+    };
+
+    use super::*;
+
+    /// Stores schedules in order, failing a batch at the first `bad` host
+    /// and losing the connection the first time it gets to a `flaky` one.
+    /// What came before in the batch stays written, and like the real
+    /// backends it leaves a schedule that's written again as it was.
+    #[derive(Default)]
+    struct PickyStorage {
+        stored: Mutex<Vec<String>>,
+        /// Every write that got to the storage, including repeated ones
+        writes: AtomicUsize,
+        dropped_connection: AtomicBool,
+    }
+
+    fn schedule(host: &str) -> PendingWrite {
+        PendingWrite::Schedule(ScheduledProbe {
+            host: host.to_owned(),
+            port: 25565,
+            next_probe_at: DateTime::now(),
+        })
+    }
+
+    #[async_trait]
+    impl Storage for PickyStorage {
+        async fn record_batch(&self, batch: &[PendingWrite]) -> StorageResult<()> {
+            for write in batch {
+                match write.server().0 {
+                    "bad" => return Err(StorageError::MongoWrite("rejected".to_owned())),
+                    "flaky" if !self.dropped_connection.swap(true, Ordering::SeqCst) => {
+                        return Err(StorageError::PostgresPool("connection lost".to_owned()));
+                    }
+                    host => {
+                        self.writes.fetch_add(1, Ordering::SeqCst);
+                        let mut stored = self.stored.lock().unwrap();
+                        if !stored.iter().any(|stored| stored == host) {
+                            stored.push(host.to_owned());
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        async fn record_server(&self, _: &ServerObservation) -> StorageResult<()> {
+            unimplemented!()
+        }
+        async fn record_failure(&self, _: &ProbeFailure) -> StorageResult<()> {
+            unimplemented!()
+        }
+        async fn record_players(&self, _: &ServerObservation) -> StorageResult<()> {
+            unimplemented!()
+        }
+        async fn schedule_probes(&self, _: &[ScheduledProbe]) -> StorageResult<()> {
+            unimplemented!()
+        }
+        async fn find_mods(&self, _: &str) -> StorageResult<Vec<ModInstall>> {
+            unimplemented!()
+        }
+        async fn version_counts(&self) -> StorageResult<Vec<VersionCount>> {
+            unimplemented!()
+        }
+        async fn online_counts(&self) -> StorageResult<Vec<OnlineCount>> {
+            unimplemented!()
+        }
+        async fn watch_targets(&self) -> StorageResult<Vec<WatchTarget>> {
+            unimplemented!()
+        }
+        async fn server_state(&self, _: &str, _: i16) -> StorageResult<Option<ServerState>> {
+            unimplemented!()
+        }
+        async fn player_counts(&self, _: &HistoryQuery) -> StorageResult<Vec<PlayerCount>> {
+            unimplemented!()
+        }
+        async fn uptime(&self, _: &HistoryQuery) -> StorageResult<Uptime> {
+            unimplemented!()
+        }
+        async fn player_sessions(&self, _: &str) -> StorageResult<Vec<PlayerSession>> {
+            unimplemented!()
+        }
+        async fn export_servers(&self, _: &ServerFilter) -> StorageResult<Vec<ServerRow>> {
+            unimplemented!()
+        }
+        async fn export_players(&self, _: &PlayerFilter) -> StorageResult<Vec<PlayerRow>> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_good_writes_of_a_rejected_batch() {
+        let storage = PickyStorage::default();
+        let mut batch = vec![schedule("a"), schedule("bad"), schedule("b")];
+
+        assert_eq!(flush(&storage, &mut batch).await, 1);
+
+        assert_eq!(*storage.stored.lock().unwrap(), ["a", "b"]);
+        assert!(batch.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stores_a_partly_written_batch_once() {
+        let storage = PickyStorage::default();
+        let mut batch = vec![schedule("a"), schedule("b"), schedule("bad"), schedule("c")];
+
+        assert_eq!(flush(&storage, &mut batch).await, 1);
+
+        // The writes before `bad` went in with the batch and again on their own
+        assert_eq!(storage.writes.load(Ordering::SeqCst), 5);
+        assert_eq!(*storage.stored.lock().unwrap(), ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn retries_after_a_lost_connection() {
+        let storage = PickyStorage::default();
+        let mut batch = vec![schedule("a"), schedule("flaky")];
+
+        assert_eq!(flush(&storage, &mut batch).await, 0);
+
+        assert_eq!(*storage.stored.lock().unwrap(), ["a", "flaky"]);
+    }
+}
@@ -0,0 +1,341 @@
+pub mod buffer;
+pub mod mongo;
+pub mod null;
+pub mod postgres;
+pub mod sqlite;
+
+use std::fmt;
+
+use async_trait::async_trait;
+use mongodb::bson::DateTime;
+
+use crate::model::{
+    failure::ProbeFailure, mods::ModInstall, observation::ServerObservation, server::ServerStatus,
+    session::PlayerSession,
+};
+
+#[derive(Debug)]
+pub enum StorageError {
+    Mongo(mongodb::error::Error),
+    Sqlite(rusqlite::Error),
+    Postgres(tokio_postgres::Error),
+    PostgresPool(String),
+    /// Statements in a batched command that the server rejected
+    MongoWrite(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Mongo(err) => write!(f, "mongodb: {}", err),
+            StorageError::Sqlite(err) => write!(f, "sqlite: {}", err),
+            StorageError::Postgres(err) => write!(f, "postgres: {}", err),
+            StorageError::PostgresPool(err) => write!(f, "postgres pool: {}", err),
+            StorageError::MongoWrite(err) => write!(f, "mongodb write: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl StorageError {
+    /// Whether the same write could go through if tried again, like after a
+    /// dropped connection or while another writer holds a lock.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            StorageError::Mongo(err) => {
+                err.contains_label("RetryableWriteError")
+                    || err.contains_label("TransientTransactionError")
+                    || matches!(
+                        *err.kind,
+                        mongodb::error::ErrorKind::Io(_)
+                            | mongodb::error::ErrorKind::ConnectionPoolCleared { .. }
+                            | mongodb::error::ErrorKind::ServerSelection { .. }
+                    )
+            }
+            StorageError::Sqlite(err) => matches!(
+                err.sqlite_error_code(),
+                Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+            ),
+            StorageError::Postgres(err) => {
+                use tokio_postgres::error::SqlState;
+
+                match err.code() {
+                    Some(code) => {
+                        code.code().starts_with("08")
+                            || [
+                                SqlState::T_R_SERIALIZATION_FAILURE,
+                                SqlState::T_R_DEADLOCK_DETECTED,
+                                SqlState::ADMIN_SHUTDOWN,
+                                SqlState::CANNOT_CONNECT_NOW,
+                                SqlState::TOO_MANY_CONNECTIONS,
+                            ]
+                            .contains(code)
+                    }
+                    // Not from the server, so the connection went away
+                    None => {
+                        err.is_closed()
+                            || std::error::Error::source(err)
+                                .is_some_and(|source| source.is::<std::io::Error>())
+                    }
+                }
+            }
+            StorageError::PostgresPool(_) => true,
+            StorageError::MongoWrite(_) => false,
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for StorageError {
+    fn from(value: mongodb::error::Error) -> Self {
+        StorageError::Mongo(value)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(value: rusqlite::Error) -> Self {
+        StorageError::Sqlite(value)
+    }
+}
+
+impl From<tokio_postgres::Error> for StorageError {
+    fn from(value: tokio_postgres::Error) -> Self {
+        StorageError::Postgres(value)
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// Number of servers seen on a protocol version.
+#[derive(Debug, Clone)]
+pub struct VersionCount {
+    pub protocol: i32,
+    pub servers: i64,
+    /// Servers whose advertised version name doesn't match the protocol
+    pub mismatched: i64,
+}
+
+/// Servers online at their last probe, and the players on them, on one
+/// protocol version.
+#[derive(Debug, Clone)]
+pub struct OnlineCount {
+    pub protocol: i32,
+    pub servers: i64,
+    pub players: i64,
+}
+
+/// Which servers to list or export.
+#[derive(Debug, Clone, Default)]
+pub struct ServerFilter {
+    pub host: Option<String>,
+    pub port: Option<i16>,
+    pub protocol: Option<i32>,
+    pub updated_after: Option<DateTime>,
+    pub updated_before: Option<DateTime>,
+    pub min_players: Option<i32>,
+    /// Most players online
+    pub max_players: Option<i32>,
+    pub status: Option<ServerStatus>,
+    /// Fingerprinted software such as `Paper`, in any case
+    pub software: Option<String>,
+    /// Servers seen with this mod, in any version
+    pub mod_id: Option<String>,
+    /// Text the MOTD contains, in any case
+    pub motd: Option<String>,
+    /// Most servers to return, busiest first
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Which players to list or export.
+#[derive(Debug, Clone, Default)]
+pub struct PlayerFilter {
+    pub uuid: Option<String>,
+    /// Name in any case
+    pub name: Option<String>,
+    pub seen_after: Option<DateTime>,
+    pub seen_before: Option<DateTime>,
+}
+
+/// Server flattened to plain columns for exporting.
+#[derive(Debug, Clone)]
+pub struct ServerRow {
+    pub host: String,
+    pub port: i16,
+    pub motd: String,
+    pub version_name: String,
+    pub protocol: i32,
+    pub online_players: i32,
+    pub max_players: i32,
+    pub software: String,
+    pub forge: bool,
+    /// `mod_id@version` separated by `;`
+    pub mods: String,
+    pub last_updated: DateTime,
+    pub status: ServerStatus,
+}
+
+/// Player flattened to plain columns for exporting.
+#[derive(Debug, Clone)]
+pub struct PlayerRow {
+    pub uuid: String,
+    pub name: String,
+    pub last_seen: DateTime,
+    pub last_updated: DateTime,
+}
+
+/// Result of a probe, or when to probe again, waiting in a `WriteBuffer` to
+/// be stored.
+#[derive(Debug, Clone)]
+pub enum PendingWrite {
+    Observation(Box<ServerObservation>),
+    Failure(ProbeFailure),
+    Schedule(ScheduledProbe),
+}
+
+impl PendingWrite {
+    /// Server the write is about.
+    pub fn server(&self) -> (&str, i16) {
+        match self {
+            PendingWrite::Observation(observation) => (&observation.host, observation.port),
+            PendingWrite::Failure(failure) => (&failure.host, failure.port),
+            PendingWrite::Schedule(probe) => (&probe.host, probe.port),
+        }
+    }
+}
+
+/// When `watch` should next probe a server.
+#[derive(Debug, Clone)]
+pub struct ScheduledProbe {
+    pub host: String,
+    pub port: i16,
+    pub next_probe_at: DateTime,
+}
+
+/// Known server as `watch` sees it, enough to decide when to probe it next.
+#[derive(Debug, Clone)]
+pub struct WatchTarget {
+    pub host: String,
+    pub port: i16,
+    pub status: ServerStatus,
+    pub consecutive_failures: i32,
+    pub online_players: i32,
+    /// `None` for servers `watch` hasn't scheduled yet
+    pub next_probe_at: Option<DateTime>,
+}
+
+/// Which part of one server's history to look at.
+#[derive(Debug, Clone)]
+pub struct HistoryQuery {
+    pub host: String,
+    pub port: i16,
+    pub since: Option<DateTime>,
+    pub until: Option<DateTime>,
+}
+
+/// Player count at one point in time.
+#[derive(Debug, Clone)]
+pub struct PlayerCount {
+    pub observed_at: DateTime,
+    pub online_players: i32,
+    pub max_players: i32,
+}
+
+/// How many probes of a server got a status response.
+#[derive(Debug, Clone, Default)]
+pub struct Uptime {
+    pub probes: i64,
+    pub online: i64,
+}
+
+impl Uptime {
+    /// Share of probes the server answered, in percent.
+    pub fn percent(&self) -> Option<f64> {
+        (self.probes > 0).then(|| self.online as f64 * 100.0 / self.probes as f64)
+    }
+}
+
+/// Where a server currently stands, see `ServerStatus`.
+#[derive(Debug, Clone)]
+pub struct ServerState {
+    pub status: ServerStatus,
+    pub status_since: Option<DateTime>,
+    pub consecutive_failures: i32,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime>,
+}
+
+/// Where scan results end up. The scan pipeline only talks to this trait so
+/// backends can be swapped without touching it.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Upserts the server the observation was made on, along with its mods
+    /// and raw response if the observation kept one, appends the observation
+    /// to the server's history and moves its player sessions along. Marks
+    /// the server online and resets its consecutive failure count.
+    async fn record_server(&self, observation: &ServerObservation) -> StorageResult<()>;
+
+    /// Appends an offline entry to the history of a server that didn't answer,
+    /// sets its last error, counts the failure, moves its status along and
+    /// closes its open sessions. Hosts that never answered aren't servers we
+    /// know about and are ignored.
+    async fn record_failure(&self, failure: &ProbeFailure) -> StorageResult<()>;
+
+    /// Upserts every player in the observation's sample.
+    async fn record_players(&self, observation: &ServerObservation) -> StorageResult<()>;
+
+    /// Sets when each server is due to be probed again. Servers we don't know
+    /// about are ignored.
+    async fn schedule_probes(&self, probes: &[ScheduledProbe]) -> StorageResult<()>;
+
+    /// Stores a batch of probe results. Backends that can should override
+    /// this to write the whole batch in as few round trips as possible.
+    ///
+    /// A failed batch may have been partly written and is written again, in
+    /// full and then one write at a time, so writing a result that is already
+    /// stored must leave it as it was.
+    async fn record_batch(&self, batch: &[PendingWrite]) -> StorageResult<()> {
+        for write in batch {
+            match write {
+                PendingWrite::Observation(observation) => {
+                    self.record_server(observation).await?;
+                    self.record_players(observation).await?;
+                }
+                PendingWrite::Failure(failure) => self.record_failure(failure).await?,
+                PendingWrite::Schedule(probe) => {
+                    self.schedule_probes(std::slice::from_ref(probe)).await?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Every server a mod has been seen on, in any version.
+    async fn find_mods(&self, mod_id: &str) -> StorageResult<Vec<ModInstall>>;
+
+    /// Server counts per protocol version, most common first.
+    async fn version_counts(&self) -> StorageResult<Vec<VersionCount>>;
+
+    /// Online servers and players per protocol version, as of each server's
+    /// last probe.
+    async fn online_counts(&self) -> StorageResult<Vec<OnlineCount>>;
+
+    /// Every known server, in no particular order.
+    async fn watch_targets(&self) -> StorageResult<Vec<WatchTarget>>;
+
+    /// `None` for servers that were never recorded.
+    async fn server_state(&self, host: &str, port: i16) -> StorageResult<Option<ServerState>>;
+
+    /// Player counts of the online probes in the query, oldest first.
+    async fn player_counts(&self, query: &HistoryQuery) -> StorageResult<Vec<PlayerCount>>;
+
+    async fn uptime(&self, query: &HistoryQuery) -> StorageResult<Uptime>;
+
+    /// Every session of a player on any server, most recent first.
+    async fn player_sessions(&self, uuid: &str) -> StorageResult<Vec<PlayerSession>>;
+
+    /// Servers matching the filter, busiest first.
+    async fn export_servers(&self, filter: &ServerFilter) -> StorageResult<Vec<ServerRow>>;
+
+    async fn export_players(&self, filter: &PlayerFilter) -> StorageResult<Vec<PlayerRow>>;
+}
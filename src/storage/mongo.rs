@@ -0,0 +1,1327 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, to_bson, to_document, to_vec, Bson, DateTime, Document};
+use mongodb::error::{CommandError, ErrorKind, WriteError, WriteFailure};
+use mongodb::options::{AggregateOptions, FindOneOptions, FindOptions, IndexOptions};
+use mongodb::{Client, Collection, Database, IndexModel};
+
+use crate::model::{
+    failure::ProbeFailure,
+    history::HistoryEntry,
+    mods::ModInstall,
+    observation::ServerObservation,
+    player::MinecraftPlayer,
+    player::ServerPlayer,
+    raw::RawResponse,
+    server::{MinecraftServer, Online, ServerStatus},
+    session::{OpenSession, PlayerSession, SessionChanges},
+    uuid::UUID,
+};
+
+use super::{
+    HistoryQuery, OnlineCount, PendingWrite, PlayerCount, PlayerFilter, PlayerRow, ScheduledProbe,
+    ServerFilter, ServerRow, ServerState, Storage, StorageError, StorageResult, Uptime,
+    VersionCount, WatchTarget,
+};
+
+/// Data migrations, applied in order at startup and recorded in
+/// `schema_migrations`, so only ever append to this list. Each one is
+/// implemented in `MongoStorage::migrate`.
+const MIGRATIONS: [&str; 3] = [
+    "move embedded historic_players into server_players",
+    "set status on servers recorded before it existed",
+    "merge duplicates the unique indexes would reject",
+];
+
+#[derive(Clone)]
+pub struct MongoStorage {
+    pub servers: Collection<MinecraftServer>,
+    pub players: Collection<MinecraftPlayer>,
+    pub mods: Collection<ModInstall>,
+    pub raw_responses: Collection<RawResponse>,
+    pub observations: Collection<HistoryEntry>,
+    pub sessions: Collection<PlayerSession>,
+    pub server_players: Collection<ServerPlayer>,
+    database: Database,
+    /// Consecutive failures after which a server is marked gone
+    gone_after: i32,
+    /// Time without a sighting after which a player's session is closed
+    session_gap: Duration,
+}
+
+impl MongoStorage {
+    pub async fn connect(
+        uri: &str,
+        database_name: &str,
+        raw_retention: Duration,
+        gone_after: i32,
+        session_gap: Duration,
+    ) -> StorageResult<Self> {
+        let mongo = Client::with_uri_str(uri).await?;
+        let database = mongo.database(database_name);
+
+        let servers = database.collection::<MinecraftServer>("servers");
+        let players = database.collection::<MinecraftPlayer>("players");
+        let mods = database.collection::<ModInstall>("mods");
+        let raw_responses = database.collection::<RawResponse>("raw_responses");
+        let observations = database.collection::<HistoryEntry>("observations");
+        let sessions = database.collection::<PlayerSession>("sessions");
+        let server_players = database.collection::<ServerPlayer>("server_players");
+
+        let storage = Self {
+            servers,
+            players,
+            mods,
+            raw_responses,
+            observations,
+            sessions,
+            server_players,
+            database,
+            gone_after,
+            session_gap,
+        };
+
+        // Migrations first, unique indexes can't be built over old duplicates
+        storage.migrate().await?;
+        storage.create_indexes(raw_retention).await?;
+
+        Ok(storage)
+    }
+
+    /// Creates any missing indexes. Safe to run on every start, existing
+    /// indexes with the same keys and options are left alone.
+    async fn create_indexes(&self, raw_retention: Duration) -> StorageResult<()> {
+        let server_indexes = vec![
+            unique_index(doc! {"host": 1, "port": 1}),
+            index(doc! {"last_updated": -1}),
+            index(doc! {"version.protocol": 1}),
+            index(doc! {"status": 1}),
+            index(doc! {"next_probe_at": 1}),
+        ];
+        let player_indexes = vec![unique_index(doc! {"uuid": 1}), index(doc! {"name": "text"})];
+        let mod_indexes = vec![unique_index(doc! {"mod_id": 1, "host": 1, "port": 1})];
+        let observation_indexes = vec![index(doc! {"host": 1, "port": 1, "observed_at": 1})];
+        let session_indexes = vec![
+            index(doc! {"host": 1, "port": 1, "ended_at": 1}),
+            index(doc! {"uuid": 1, "started_at": -1}),
+        ];
+        // Looked up by server when recording, by player when searching
+        let server_player_indexes = vec![
+            unique_index(doc! {"host": 1, "port": 1, "uuid": 1}),
+            index(doc! {"uuid": 1, "last_seen": -1}),
+        ];
+
+        let (servers, players, mods, observations, sessions, server_players, raw) = tokio::join!(
+            self.servers.create_indexes(server_indexes, None),
+            self.players.create_indexes(player_indexes, None),
+            self.mods.create_indexes(mod_indexes, None),
+            self.observations.create_indexes(observation_indexes, None),
+            self.sessions.create_indexes(session_indexes, None),
+            self.server_players
+                .create_indexes(server_player_indexes, None),
+            self.expire_raw_responses(raw_retention)
+        );
+        servers?;
+        players?;
+        mods?;
+        observations?;
+        sessions?;
+        server_players?;
+        raw?;
+
+        Ok(())
+    }
+
+    /// TTL index that has Mongo delete raw responses once they're older than
+    /// `retention`, updated in place if the retention changed.
+    async fn expire_raw_responses(&self, retention: Duration) -> StorageResult<()> {
+        let name = "received_ttl";
+        let ttl_index = IndexModel::builder()
+            .keys(doc! {"received": 1})
+            .options(
+                IndexOptions::builder()
+                    .name(name.to_owned())
+                    .expire_after(retention)
+                    .build(),
+            )
+            .build();
+
+        match self.raw_responses.create_index(ttl_index, None).await {
+            Ok(_) => Ok(()),
+            Err(err) if is_index_conflict(&err) => {
+                let command = doc! {
+                    "collMod": "raw_responses",
+                    "index": {"name": name, "expireAfterSeconds": retention.as_secs() as i64},
+                };
+                self.database.run_command(command, None).await?;
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn migrate(&self) -> StorageResult<()> {
+        let migrations = self.database.collection::<Document>("schema_migrations");
+        let applied = migrations
+            .find_one(
+                None,
+                FindOneOptions::builder().sort(doc! {"_id": -1}).build(),
+            )
+            .await?
+            .and_then(|migration| migration.get_i32("_id").ok())
+            .unwrap_or(0);
+
+        for (version, description) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+            let version = version as i32 + 1;
+            match version {
+                1 => self.migrate_historic_players().await?,
+                2 => self.migrate_status().await?,
+                3 => self.migrate_duplicates().await?,
+                _ => unreachable!("migration {} has no implementation", version),
+            }
+
+            let record = doc! {
+                "_id": version,
+                "description": *description,
+                "applied_at": DateTime::now(),
+            };
+            match migrations.insert_one(record, None).await {
+                Ok(_) => {}
+                // Another instance starting at the same time got there first
+                Err(err) if is_duplicate_key(&err) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs many update statements in one `update` command. Driver 2.x has
+    /// no bulk write, this is what it would send for one anyway.
+    async fn bulk_update(&self, collection: &str, updates: Vec<Document>) -> StorageResult<()> {
+        for chunk in command_chunks(&updates) {
+            let command = doc! {"update": collection, "updates": chunk, "ordered": false};
+            let reply = self.database.run_command(command, None).await?;
+
+            if let Ok(errors) = reply.get_array("writeErrors") {
+                let first = errors
+                    .first()
+                    .and_then(|err| err.as_document())
+                    .and_then(|err| err.get_str("errmsg").ok())
+                    .unwrap_or_default();
+                return Err(StorageError::MongoWrite(format!(
+                    "{} of {} updates to {} failed, first: {}",
+                    errors.len(),
+                    chunk.len(),
+                    collection,
+                    first
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves players embedded in server documents under `historic_players`,
+    /// the way they used to be stored, into `server_players`.
+    async fn migrate_historic_players(&self) -> StorageResult<()> {
+        let servers = self.servers.clone_with_type::<Document>();
+        let options = FindOptions::builder()
+            .projection(doc! {"host": 1, "port": 1, "historic_players": 1})
+            .build();
+        let mut cursor = servers
+            .find(doc! {"historic_players": {"$exists": true}}, options)
+            .await?;
+
+        while let Some(server) = cursor.try_next().await? {
+            let host = server.get_str("host").unwrap_or_default();
+            let port = server.get_i32("port").unwrap_or_default();
+
+            // Keyed by uuid, or a plain array in the oldest documents
+            let historic_players: Vec<&Bson> = match server.get("historic_players") {
+                Some(Bson::Document(players)) => players.values().collect(),
+                Some(Bson::Array(players)) => players.iter().collect(),
+                _ => Vec::new(),
+            };
+
+            let mut links = Vec::new();
+            for player in historic_players.iter().filter_map(|p| p.as_document()) {
+                let (Ok(uuid), Ok(last_seen)) =
+                    (player.get_str("uuid"), player.get_datetime("last_seen"))
+                else {
+                    continue;
+                };
+
+                links.push(upsert(
+                    doc! {"host": host, "port": port, "uuid": uuid},
+                    doc! {
+                        "$min": {"first_seen": last_seen},
+                        "$max": {"last_seen": last_seen},
+                        "$setOnInsert": {"times_seen": 1},
+                    },
+                ));
+            }
+            self.bulk_update("server_players", links).await?;
+
+            servers
+                .update_one(
+                    doc! {"_id": server.get("_id").cloned().unwrap_or(Bson::Null)},
+                    doc! {"$unset": {"historic_players": ""}},
+                    None,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn migrate_status(&self) -> StorageResult<()> {
+        self.servers
+            .update_many(
+                doc! {"status": {"$exists": false}},
+                vec![doc! {"$set": {
+                    "status": {"$cond": [
+                        {"$gt": [{"$ifNull": ["$consecutive_failures", 0]}, 0]},
+                        "unreachable",
+                        "online",
+                    ]},
+                    "status_since": {"$ifNull": ["$last_error_at", "$last_updated"]},
+                }}],
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Merges documents that share what is now a unique key, written before
+    /// the indexes existed or while an index build had failed. The newest
+    /// document of each group is kept, server players add up their sightings.
+    async fn migrate_duplicates(&self) -> StorageResult<()> {
+        // Players still embedded in duplicate servers would go with them
+        self.migrate_historic_players().await?;
+
+        let duplicates = [
+            (
+                "servers",
+                doc! {"host": "$host", "port": "$port"},
+                "last_updated",
+            ),
+            ("players", doc! {"uuid": "$uuid"}, "last_updated"),
+            (
+                "mods",
+                doc! {"mod_id": "$mod_id", "host": "$host", "port": "$port"},
+                "last_seen",
+            ),
+        ];
+        for (collection, key, newest) in duplicates {
+            let groups = self
+                .duplicate_groups(collection, key, newest, doc! {})
+                .await?;
+            self.delete_duplicates(collection, &groups).await?;
+        }
+
+        let groups = self
+            .duplicate_groups(
+                "server_players",
+                doc! {"host": "$host", "port": "$port", "uuid": "$uuid"},
+                "last_seen",
+                doc! {
+                    "first_seen": {"$min": "$first_seen"},
+                    "last_seen": {"$max": "$last_seen"},
+                    "times_seen": {"$sum": "$times_seen"},
+                },
+            )
+            .await?;
+        let merged = groups
+            .iter()
+            .filter_map(|group| {
+                let keep = group.get_array("ids").ok()?.first()?.clone();
+                Some(doc! {
+                    "q": {"_id": keep},
+                    "u": {"$set": {
+                        "first_seen": group.get("first_seen")?.clone(),
+                        "last_seen": group.get("last_seen")?.clone(),
+                        "times_seen": group.get("times_seen")?.clone(),
+                    }},
+                })
+            })
+            .collect();
+        self.bulk_update("server_players", merged).await?;
+        self.delete_duplicates("server_players", &groups).await
+    }
+
+    /// Groups of documents in `collection` with the same `key`, each with the
+    /// `ids` of its documents newest first by the `newest` field, along with
+    /// whatever `accumulators` adds up over the group.
+    async fn duplicate_groups(
+        &self,
+        collection: &str,
+        key: Document,
+        newest: &str,
+        accumulators: Document,
+    ) -> StorageResult<Vec<Document>> {
+        let mut group = doc! {"_id": key, "ids": {"$push": "$_id"}, "count": {"$sum": 1}};
+        group.extend(accumulators);
+        let pipeline = [
+            doc! {"$sort": {newest: -1}},
+            doc! {"$group": group},
+            doc! {"$match": {"count": {"$gt": 1}}},
+        ];
+        // Sorting and grouping a whole collection goes over the memory limit
+        let options = AggregateOptions::builder().allow_disk_use(true).build();
+
+        Ok(self
+            .database
+            .collection::<Document>(collection)
+            .aggregate(pipeline, options)
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    /// Deletes every document of each group but the first, the newest.
+    async fn delete_duplicates(&self, collection: &str, groups: &[Document]) -> StorageResult<()> {
+        let older: Vec<Bson> = groups
+            .iter()
+            .filter_map(|group| group.get_array("ids").ok())
+            .flat_map(|ids| ids.iter().skip(1).cloned())
+            .collect();
+
+        for chunk in older.chunks(10_000) {
+            self.database
+                .collection::<Document>(collection)
+                .delete_many(doc! {"_id": {"$in": chunk}}, None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the mods each server stopped listing, once its latest mods are
+    /// upserted. A truncated list doesn't say which mods are gone.
+    async fn remove_stale_mods(&self, observations: &[&ServerObservation]) -> StorageResult<()> {
+        let deletes: Vec<Document> = observations
+            .iter()
+            .filter(|o| !o.mods_truncated)
+            .map(|o| {
+                doc! {
+                    "q": {
+                        "host": o.host.clone(),
+                        "port": o.port as i32,
+                        "last_seen": {"$lt": o.observed_at},
+                    },
+                    "limit": 0,
+                }
+            })
+            .collect();
+
+        for chunk in command_chunks(&deletes) {
+            let command = doc! {"delete": "mods", "deletes": chunk, "ordered": false};
+            let reply = self.database.run_command(command, None).await?;
+            if let Ok(errors) = reply.get_array("writeErrors") {
+                return Err(StorageError::MongoWrite(format!(
+                    "{} of {} stale mod deletes failed",
+                    errors.len(),
+                    chunk.len()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Open sessions on each of the servers, keyed by server and then player.
+    async fn open_sessions(
+        &self,
+        servers: Vec<Document>,
+    ) -> StorageResult<HashMap<(String, i16), HashMap<UUID, OpenSession>>> {
+        let mut open = HashMap::<_, HashMap<_, _>>::new();
+        if servers.is_empty() {
+            return Ok(open);
+        }
+
+        let mut cursor = self
+            .sessions
+            .find(doc! {"ended_at": null, "$or": servers}, None)
+            .await?;
+        while let Some(session) = cursor.try_next().await? {
+            open.entry((session.host, session.port))
+                .or_default()
+                .insert(
+                    session.uuid,
+                    OpenSession {
+                        started_at: session.started_at,
+                        last_seen: session.last_seen,
+                    },
+                );
+        }
+
+        Ok(open)
+    }
+
+    /// Servers out of `servers` that have been recorded before.
+    async fn known_servers(&self, servers: Vec<Document>) -> StorageResult<HashSet<(String, i16)>> {
+        if servers.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let options = FindOptions::builder()
+            .projection(doc! {"host": 1, "port": 1})
+            .build();
+        let known: Vec<Document> = self
+            .servers
+            .clone_with_type::<Document>()
+            .find(doc! {"$or": servers}, options)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(known
+            .iter()
+            .map(|server| {
+                (
+                    server.get_str("host").unwrap_or_default().to_owned(),
+                    server.get_i32("port").unwrap_or_default() as i16,
+                )
+            })
+            .collect())
+    }
+
+    async fn record_servers(&self, observations: &[&ServerObservation]) -> StorageResult<()> {
+        if observations.is_empty() {
+            return Ok(());
+        }
+
+        let open = self
+            .open_sessions(
+                observations
+                    .iter()
+                    .map(|o| server_key(&o.host, o.port))
+                    .collect(),
+            )
+            .await?;
+
+        let mut servers = Vec::new();
+        let mut sessions = Vec::new();
+        let mut server_players = Vec::new();
+        let mut mods = Vec::new();
+        let mut history = Vec::new();
+        let mut raw_responses = Vec::new();
+
+        for observation in observations {
+            let changes = SessionChanges::new(
+                open.get(&(observation.host.clone(), observation.port))
+                    .unwrap_or(&HashMap::new()),
+                observation,
+                self.session_gap,
+            );
+
+            servers.push(server_update(observation, &changes));
+            servers.push(doc! {
+                "q": {
+                    "host": observation.host.clone(),
+                    "port": observation.port as i32,
+                    "status": {"$ne": ServerStatus::Online.as_str()},
+                },
+                "u": {"$set": {
+                    "status": ServerStatus::Online.as_str(),
+                    "status_since": observation.observed_at,
+                }},
+            });
+            sessions.extend(session_updates(observation, &changes));
+            server_players.extend(
+                observation
+                    .known_online()
+                    .map(|online_player| server_player_update(observation, &online_player.uuid)),
+            );
+            mods.extend(observation.mods.iter().map(|server_mod| {
+                upsert(
+                    doc! {
+                        "mod_id": server_mod.mod_id.clone(),
+                        "host": observation.host.clone(),
+                        "port": observation.port as i32,
+                    },
+                    doc! {
+                        "$setOnInsert": {"first_seen": observation.observed_at},
+                        "$set": {"version": server_mod.version.clone(), "last_seen": observation.observed_at},
+                    },
+                )
+            }));
+            history.push(history_insert(&HistoryEntry::online(observation)));
+            if let Some(status) = &observation.raw {
+                let raw = RawResponse {
+                    host: observation.host.clone(),
+                    port: observation.port,
+                    received: observation.observed_at,
+                    status: status.clone(),
+                };
+                raw_responses.push(insert_once(
+                    doc! {
+                        "host": observation.host.clone(),
+                        "port": observation.port as i32,
+                        "received": observation.observed_at,
+                    },
+                    to_document(&raw).unwrap(),
+                ));
+            }
+        }
+
+        let (sres, pres, lres, mres, hres, rres) = tokio::join!(
+            self.bulk_update("servers", servers),
+            self.bulk_update("sessions", sessions),
+            self.bulk_update("server_players", server_players),
+            self.bulk_update("mods", mods),
+            self.bulk_update("observations", history),
+            self.bulk_update("raw_responses", raw_responses)
+        );
+        sres?;
+        pres?;
+        lres?;
+        mres?;
+        hres?;
+        rres?;
+
+        self.remove_stale_mods(observations).await
+    }
+
+    async fn record_failures(&self, failures: &[&ProbeFailure]) -> StorageResult<()> {
+        let known = self
+            .known_servers(
+                failures
+                    .iter()
+                    .map(|f| server_key(&f.host, f.port))
+                    .collect(),
+            )
+            .await?;
+
+        let failures: Vec<_> = failures
+            .iter()
+            .filter(|f| known.contains(&(f.host.clone(), f.port)))
+            .collect();
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        // Pipeline updates, so the status can depend on the failure count.
+        // A failure is only counted if nothing at or after it has been, so
+        // writing it again after a partly failed batch doesn't count it twice.
+        let servers = failures
+            .iter()
+            .map(|f| {
+                doc! {
+                    "q": {
+                        "host": f.host.clone(),
+                        "port": f.port as i32,
+                        "last_updated": {"$not": {"$gte": f.observed_at}},
+                        "last_error_at": {"$not": {"$gte": f.observed_at}},
+                    },
+                    "u": [
+                        {"$set": {
+                            "last_error": {"$literal": f.error.clone()},
+                            "last_error_message": {"$literal": f.message.clone()},
+                            "last_error_at": f.observed_at,
+                            "consecutive_failures": {
+                                "$add": [{"$ifNull": ["$consecutive_failures", 0]}, 1],
+                            },
+                        }},
+                        {"$set": {"next_status": {"$cond": [
+                            {"$gte": ["$consecutive_failures", self.gone_after]},
+                            ServerStatus::Gone.as_str(),
+                            {"$literal": f.status.as_str()},
+                        ]}}},
+                        {"$set": {
+                            "status_since": {"$cond": [
+                                {"$eq": ["$status", "$next_status"]},
+                                "$status_since",
+                                f.observed_at,
+                            ]},
+                            "status": "$next_status",
+                        }},
+                        {"$unset": "next_status"},
+                    ],
+                }
+            })
+            .collect();
+        let history = failures
+            .iter()
+            .map(|f| history_insert(&HistoryEntry::offline(f)))
+            .collect();
+        let closes = failures
+            .iter()
+            .map(|f| {
+                close_sessions(
+                    doc! {"host": f.host.clone(), "port": f.port as i32, "ended_at": null},
+                )
+            })
+            .collect();
+
+        let (sres, hres, cres) = tokio::join!(
+            self.bulk_update("servers", servers),
+            self.bulk_update("observations", history),
+            self.bulk_update("sessions", closes)
+        );
+        sres?;
+        hres?;
+        cres?;
+        Ok(())
+    }
+
+    async fn record_schedules(&self, probes: &[&ScheduledProbe]) -> StorageResult<()> {
+        let updates = probes
+            .iter()
+            .map(|probe| {
+                doc! {
+                    "q": server_key(&probe.host, probe.port),
+                    "u": {"$set": {"next_probe_at": probe.next_probe_at}},
+                }
+            })
+            .collect();
+        self.bulk_update("servers", updates).await
+    }
+
+    async fn record_sample_players(
+        &self,
+        observations: &[&ServerObservation],
+    ) -> StorageResult<()> {
+        let now = DateTime::now();
+        let players = observations
+            .iter()
+            .flat_map(|observation| {
+                observation.known_players().map(|player| {
+                    upsert(
+                        doc! {"uuid": player.id.0.clone()},
+                        doc! {
+                            "$setOnInsert": {"uuid": player.id.0.clone()},
+                            "$set": {
+                                "name": player.name.clone(),
+                                "last_seen": observation.observed_at,
+                                "last_updated": now,
+                            },
+                        },
+                    )
+                })
+            })
+            .collect();
+
+        self.bulk_update("players", players).await
+    }
+}
+
+fn index(keys: Document) -> IndexModel {
+    IndexModel::builder().keys(keys).build()
+}
+
+fn unique_index(keys: Document) -> IndexModel {
+    IndexModel::builder()
+        .keys(keys)
+        .options(IndexOptions::builder().unique(true).build())
+        .build()
+}
+
+/// A write was rejected by a unique index.
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    const DUPLICATE_KEY: i32 = 11000;
+    matches!(
+        &*err.kind,
+        ErrorKind::Write(WriteFailure::WriteError(WriteError {
+            code: DUPLICATE_KEY,
+            ..
+        }))
+    )
+}
+
+/// Server limit on statements in one write command.
+const MAX_STATEMENTS: usize = 100_000;
+
+/// Room left for the statements of one write command. Commands sent with
+/// `run_command` are a single document, capped at 16MiB, and the rest of the
+/// command needs a little of that.
+const MAX_STATEMENT_BYTES: usize = 16 * 1024 * 1024 - 64 * 1024;
+
+/// Splits write statements into runs that fit in one command, both by count
+/// and by encoded size.
+fn command_chunks(statements: &[Document]) -> Vec<&[Document]> {
+    let mut chunks = Vec::new();
+    let (mut start, mut bytes) = (0, 0);
+
+    for (i, statement) in statements.iter().enumerate() {
+        // Each array element also carries its type byte, index key and nul
+        let size = to_vec(statement).map_or(0, |encoded| encoded.len()) + 8;
+        if i > start && (i - start == MAX_STATEMENTS || bytes + size > MAX_STATEMENT_BYTES) {
+            chunks.push(&statements[start..i]);
+            (start, bytes) = (i, 0);
+        }
+        bytes += size;
+    }
+    if start < statements.len() {
+        chunks.push(&statements[start..]);
+    }
+    chunks
+}
+
+/// An index with these keys already exists with different options.
+fn is_index_conflict(err: &mongodb::error::Error) -> bool {
+    const INDEX_OPTIONS_CONFLICT: i32 = 85;
+    matches!(
+        *err.kind,
+        ErrorKind::Command(CommandError {
+            code: INDEX_OPTIONS_CONFLICT,
+            ..
+        })
+    )
+}
+
+/// Statement for the `update` command that upserts one document.
+fn upsert(query: Document, update: impl Into<Bson>) -> Document {
+    doc! {"q": query, "u": update.into(), "upsert": true}
+}
+
+/// Statement that inserts `document` unless one matching `query` is there
+/// already, so a batch written again after it partly failed doesn't add it
+/// twice.
+fn insert_once(query: Document, document: Document) -> Document {
+    upsert(query, doc! {"$setOnInsert": document})
+}
+
+/// Statement that adds a probe's entry to the server's history.
+fn history_insert(entry: &HistoryEntry) -> Document {
+    insert_once(
+        doc! {
+            "host": entry.host.clone(),
+            "port": entry.port as i32,
+            "observed_at": entry.observed_at,
+        },
+        to_document(entry).unwrap(),
+    )
+}
+
+/// Statement that records a sighting of a player on the server. A pipeline,
+/// so `times_seen` only counts sightings newer than the last one and seeing
+/// the same observation again leaves it as it was.
+fn server_player_update(observation: &ServerObservation, uuid: &UUID) -> Document {
+    let seen = observation.observed_at;
+    upsert(
+        doc! {
+            "host": observation.host.clone(),
+            "port": observation.port as i32,
+            "uuid": uuid.0.clone(),
+        },
+        vec![doc! {"$set": {
+            "first_seen": {"$ifNull": ["$first_seen", seen]},
+            "last_seen": {"$max": ["$last_seen", seen]},
+            "times_seen": {"$cond": [
+                {"$lt": ["$last_seen", seen]},
+                {"$add": [{"$ifNull": ["$times_seen", 0]}, 1]},
+                "$times_seen",
+            ]},
+        }}],
+    )
+}
+
+/// Statement that ends the matching sessions at the time their player was
+/// last seen.
+fn close_sessions(query: Document) -> Document {
+    doc! {"q": query, "u": [{"$set": {"ended_at": "$last_seen"}}], "multi": true}
+}
+
+fn server_key(host: &str, port: i16) -> Document {
+    doc! {"host": host, "port": port as i32}
+}
+
+fn server_update(observation: &ServerObservation, changes: &SessionChanges) -> Document {
+    let online = Online {
+        list: changes.online.iter().cloned().collect(),
+        ..observation.online.clone()
+    };
+
+    let mut set_on_insert = doc! {};
+
+    set_on_insert.insert("host", observation.host.clone());
+    set_on_insert.insert("port", observation.port as i32);
+    set_on_insert.insert("whitelist", false);
+    set_on_insert.insert("status", ServerStatus::Online.as_str());
+    set_on_insert.insert("status_since", observation.observed_at);
+
+    let mut set = doc! {};
+
+    set.insert("online", to_bson(&online).unwrap());
+    set.insert("motd", observation.motd.clone());
+    set.insert("version", to_bson(&observation.version).unwrap());
+    set.insert("last_updated", observation.observed_at);
+    set.insert("forge", observation.forge);
+    set.insert("mods", to_bson(&observation.mods).unwrap());
+    set.insert(
+        "forge_channels",
+        to_bson(&observation.forge_channels).unwrap(),
+    );
+    set.insert("mods_truncated", observation.mods_truncated);
+    set.insert(
+        "parse_warnings",
+        to_bson(&observation.parse_warnings).unwrap(),
+    );
+    set.insert("enforces_secure_chat", observation.enforces_secure_chat);
+    set.insert("previews_chat", observation.previews_chat);
+    set.insert("prevents_chat_reports", observation.prevents_chat_reports);
+    set.insert("is_modded", observation.is_modded);
+    set.insert("mod_info_type", observation.mod_info_type.clone());
+    set.insert("extensions", to_bson(&observation.extensions).unwrap());
+    set.insert("software", to_bson(&observation.software).unwrap());
+    set.insert(
+        "resolved_version",
+        to_bson(&observation.resolved_version).unwrap(),
+    );
+    set.insert("consecutive_failures", 0);
+
+    upsert(
+        server_key(&observation.host, observation.port),
+        doc! {"$setOnInsert": set_on_insert, "$set": set},
+    )
+}
+
+/// The updates go out unordered, so each only matches the sessions it's
+/// meant for: a player whose session went stale gets a new one by start
+/// time, and closing skips sessions this observation extends.
+fn session_updates(observation: &ServerObservation, changes: &SessionChanges) -> Vec<Document> {
+    let mut updates: Vec<Document> = changes
+        .online
+        .iter()
+        .map(|player| {
+            upsert(
+                doc! {
+                    "uuid": player.uuid.0.clone(),
+                    "host": observation.host.clone(),
+                    "port": observation.port as i32,
+                    "started_at": player.online_since,
+                    "ended_at": null,
+                },
+                doc! {"$set": {"last_seen": observation.observed_at}},
+            )
+        })
+        .collect();
+
+    if !changes.ended.is_empty() {
+        let uuids: Vec<&str> = changes.ended.iter().map(|uuid| uuid.0.as_str()).collect();
+        updates.push(close_sessions(doc! {
+            "host": observation.host.clone(),
+            "port": observation.port as i32,
+            "uuid": {"$in": uuids},
+            "last_seen": {"$lt": observation.observed_at},
+            "ended_at": null,
+        }));
+    }
+
+    updates
+}
+
+fn history_query(query: &HistoryQuery) -> Document {
+    let mut filter = doc! {"host": query.host.clone(), "port": query.port as i32};
+    if let Some(range) = time_range(query.since, query.until) {
+        filter.insert("observed_at", range);
+    }
+    filter
+}
+
+/// `{"$gte": after, "$lt": before}` with whichever bounds are set.
+fn time_range(after: Option<DateTime>, before: Option<DateTime>) -> Option<Document> {
+    let mut range = doc! {};
+    if let Some(after) = after {
+        range.insert("$gte", after);
+    }
+    if let Some(before) = before {
+        range.insert("$lt", before);
+    }
+    (!range.is_empty()).then_some(range)
+}
+
+/// Matches `value` in any case.
+fn exact_ignoring_case(value: &str) -> Document {
+    doc! {"$regex": format!("^{}$", escape_regex(value)), "$options": "i"}
+}
+
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\.^$|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn server_row(server: &Document) -> ServerRow {
+    let version = server.get_document("version").ok();
+    let online = server.get_document("online").ok();
+    let mods = server
+        .get_array("mods")
+        .map(|mods| {
+            mods.iter()
+                .filter_map(|m| m.as_document())
+                .map(|m| {
+                    format!(
+                        "{}@{}",
+                        m.get_str("mod_id").unwrap_or_default(),
+                        m.get_str("version").unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(";")
+        })
+        .unwrap_or_default();
+
+    ServerRow {
+        host: server.get_str("host").unwrap_or_default().to_owned(),
+        port: server.get_i32("port").unwrap_or_default() as i16,
+        motd: server.get_str("motd").unwrap_or_default().to_owned(),
+        version_name: version
+            .and_then(|v| v.get_str("name").ok())
+            .unwrap_or_default()
+            .to_owned(),
+        protocol: version
+            .and_then(|v| v.get_i32("protocol").ok())
+            .unwrap_or(-1),
+        online_players: online
+            .and_then(|o| o.get_i32("players").ok())
+            .unwrap_or_default(),
+        max_players: online
+            .and_then(|o| o.get_i32("max").ok())
+            .unwrap_or_default(),
+        software: server
+            .get_document("software")
+            .and_then(|s| s.get_str("software"))
+            .unwrap_or("Unknown")
+            .to_owned(),
+        forge: server.get_bool("forge").unwrap_or_default(),
+        mods,
+        last_updated: server
+            .get_datetime("last_updated")
+            .copied()
+            .unwrap_or(DateTime::MIN),
+        status: server
+            .get_str("status")
+            .ok()
+            .and_then(ServerStatus::parse)
+            .unwrap_or_default(),
+    }
+}
+
+#[async_trait]
+impl Storage for MongoStorage {
+    async fn record_server(&self, observation: &ServerObservation) -> StorageResult<()> {
+        self.record_servers(&[observation]).await
+    }
+
+    async fn record_failure(&self, failure: &ProbeFailure) -> StorageResult<()> {
+        self.record_failures(&[failure]).await
+    }
+
+    async fn record_players(&self, observation: &ServerObservation) -> StorageResult<()> {
+        self.record_sample_players(&[observation]).await
+    }
+
+    async fn schedule_probes(&self, probes: &[ScheduledProbe]) -> StorageResult<()> {
+        self.record_schedules(&probes.iter().collect::<Vec<_>>())
+            .await
+    }
+
+    async fn record_batch(&self, batch: &[PendingWrite]) -> StorageResult<()> {
+        let mut observations = Vec::new();
+        let mut failures = Vec::new();
+        let mut schedules = Vec::new();
+        for write in batch {
+            match write {
+                PendingWrite::Observation(observation) => observations.push(observation.as_ref()),
+                PendingWrite::Failure(failure) => failures.push(failure),
+                PendingWrite::Schedule(probe) => schedules.push(probe),
+            }
+        }
+
+        let (sres, pres, fres, qres) = tokio::join!(
+            self.record_servers(&observations),
+            self.record_sample_players(&observations),
+            self.record_failures(&failures),
+            self.record_schedules(&schedules)
+        );
+        sres?;
+        pres?;
+        fres?;
+        qres?;
+        Ok(())
+    }
+
+    async fn find_mods(&self, mod_id: &str) -> StorageResult<Vec<ModInstall>> {
+        let cursor = self.mods.find(doc! {"mod_id": mod_id}, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn version_counts(&self) -> StorageResult<Vec<VersionCount>> {
+        let pipeline = vec![
+            doc! {"$group": {
+                "_id": "$version.protocol",
+                "servers": {"$sum": 1},
+                "mismatched": {"$sum": {"$cond": ["$resolved_version.mismatch", 1, 0]}},
+            }},
+            doc! {"$sort": {"servers": -1}},
+        ];
+
+        let rows: Vec<_> = self
+            .servers
+            .aggregate(pipeline, None)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| VersionCount {
+                protocol: row.get_i32("_id").unwrap_or(-1),
+                servers: row.get_i32("servers").unwrap_or(0) as i64,
+                mismatched: row.get_i32("mismatched").unwrap_or(0) as i64,
+            })
+            .collect())
+    }
+
+    async fn online_counts(&self) -> StorageResult<Vec<OnlineCount>> {
+        let pipeline = vec![
+            doc! {"$match": {"status": ServerStatus::Online.as_str()}},
+            doc! {"$group": {
+                "_id": "$version.protocol",
+                "servers": {"$sum": 1},
+                "players": {"$sum": {"$toLong": "$online.players"}},
+            }},
+        ];
+
+        let rows: Vec<_> = self
+            .servers
+            .aggregate(pipeline, None)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| OnlineCount {
+                protocol: row.get_i32("_id").unwrap_or(-1),
+                servers: row.get_i32("servers").unwrap_or(0) as i64,
+                players: row.get_i64("players").unwrap_or(0),
+            })
+            .collect())
+    }
+
+    async fn watch_targets(&self) -> StorageResult<Vec<WatchTarget>> {
+        let options = FindOptions::builder()
+            .projection(doc! {
+                "host": 1,
+                "port": 1,
+                "status": 1,
+                "consecutive_failures": 1,
+                "online.players": 1,
+                "next_probe_at": 1,
+            })
+            .build();
+        let servers: Vec<Document> = self
+            .servers
+            .clone_with_type::<Document>()
+            .find(None, options)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(servers
+            .iter()
+            .map(|server| WatchTarget {
+                host: server.get_str("host").unwrap_or_default().to_owned(),
+                port: server.get_i32("port").unwrap_or_default() as i16,
+                status: server
+                    .get_str("status")
+                    .ok()
+                    .and_then(ServerStatus::parse)
+                    .unwrap_or_default(),
+                consecutive_failures: server.get_i32("consecutive_failures").unwrap_or_default(),
+                online_players: server
+                    .get_document("online")
+                    .and_then(|online| online.get_i32("players"))
+                    .unwrap_or_default(),
+                next_probe_at: server.get_datetime("next_probe_at").ok().copied(),
+            })
+            .collect())
+    }
+
+    async fn server_state(&self, host: &str, port: i16) -> StorageResult<Option<ServerState>> {
+        let server = self.servers.find_one(server_key(host, port), None).await?;
+
+        Ok(server.map(|server| ServerState {
+            status: server.status,
+            status_since: server.status_since,
+            consecutive_failures: server.consecutive_failures,
+            last_error: server.last_error,
+            last_error_at: server.last_error_at,
+        }))
+    }
+
+    async fn player_counts(&self, query: &HistoryQuery) -> StorageResult<Vec<PlayerCount>> {
+        let mut filter = history_query(query);
+        filter.insert("online", true);
+
+        let options = FindOptions::builder().sort(doc! {"observed_at": 1}).build();
+        let entries: Vec<HistoryEntry> = self
+            .observations
+            .find(filter, options)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(entries
+            .into_iter()
+            .map(|entry| PlayerCount {
+                observed_at: entry.observed_at,
+                online_players: entry.online_players.unwrap_or_default(),
+                max_players: entry.max_players.unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn uptime(&self, query: &HistoryQuery) -> StorageResult<Uptime> {
+        let pipeline = vec![
+            doc! {"$match": history_query(query)},
+            doc! {"$group": {
+                "_id": null,
+                "probes": {"$sum": 1},
+                "online": {"$sum": {"$cond": ["$online", 1, 0]}},
+            }},
+        ];
+
+        let rows: Vec<_> = self
+            .observations
+            .aggregate(pipeline, None)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(rows
+            .first()
+            .map(|row| Uptime {
+                probes: row.get_i32("probes").unwrap_or(0) as i64,
+                online: row.get_i32("online").unwrap_or(0) as i64,
+            })
+            .unwrap_or_default())
+    }
+
+    async fn player_sessions(&self, uuid: &str) -> StorageResult<Vec<PlayerSession>> {
+        let options = FindOptions::builder().sort(doc! {"started_at": -1}).build();
+        let cursor = self.sessions.find(doc! {"uuid": uuid}, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn export_servers(&self, filter: &ServerFilter) -> StorageResult<Vec<ServerRow>> {
+        let mut query = doc! {};
+        if let Some(protocol) = filter.protocol {
+            query.insert("version.protocol", protocol);
+        }
+        if let Some(range) = time_range(filter.updated_after, filter.updated_before) {
+            query.insert("last_updated", range);
+        }
+        let mut players = doc! {};
+        if let Some(min_players) = filter.min_players {
+            players.insert("$gte", min_players);
+        }
+        if let Some(max_players) = filter.max_players {
+            players.insert("$lte", max_players);
+        }
+        if !players.is_empty() {
+            query.insert("online.players", players);
+        }
+        if let Some(status) = filter.status {
+            query.insert("status", status.as_str());
+        }
+        if let Some(host) = &filter.host {
+            query.insert("host", host);
+        }
+        if let Some(port) = filter.port {
+            query.insert("port", port as i32);
+        }
+        if let Some(software) = &filter.software {
+            query.insert("software.software", exact_ignoring_case(software));
+        }
+        if let Some(mod_id) = &filter.mod_id {
+            query.insert("mods.mod_id", mod_id);
+        }
+        if let Some(motd) = &filter.motd {
+            query.insert("motd", doc! {"$regex": escape_regex(motd), "$options": "i"});
+        }
+
+        // Only sorted when paging, sorting a full export needs too much memory
+        let options = (filter.limit.is_some() || filter.offset.is_some()).then(|| {
+            FindOptions::builder()
+                .sort(doc! {"online.players": -1, "host": 1, "port": 1})
+                .limit(filter.limit)
+                .skip(filter.offset.map(|offset| offset.max(0) as u64))
+                .build()
+        });
+
+        let servers: Vec<Document> = self
+            .servers
+            .clone_with_type::<Document>()
+            .find(query, options)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(servers.iter().map(server_row).collect())
+    }
+
+    async fn export_players(&self, filter: &PlayerFilter) -> StorageResult<Vec<PlayerRow>> {
+        let mut query = doc! {};
+        if let Some(range) = time_range(filter.seen_after, filter.seen_before) {
+            query.insert("last_seen", range);
+        }
+        if let Some(uuid) = &filter.uuid {
+            query.insert("uuid", uuid);
+        }
+        if let Some(name) = &filter.name {
+            query.insert("name", exact_ignoring_case(name));
+        }
+
+        let players: Vec<Document> = self
+            .players
+            .clone_with_type::<Document>()
+            .find(query, None)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(players
+            .iter()
+            .map(|player| PlayerRow {
+                uuid: player.get_str("uuid").unwrap_or_default().to_owned(),
+                name: player.get_str("name").unwrap_or_default().to_owned(),
+                last_seen: player
+                    .get_datetime("last_seen")
+                    .copied()
+                    .unwrap_or(DateTime::MIN),
+                last_updated: player
+                    .get_datetime("last_updated")
+                    .copied()
+                    .unwrap_or(DateTime::MIN),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_commands_by_count_and_size() {
+        let small = vec![doc! {"q": {"_id": 1}}; MAX_STATEMENTS + 1];
+        let sizes: Vec<usize> = command_chunks(&small).iter().map(|c| c.len()).collect();
+        assert_eq!(sizes, vec![MAX_STATEMENTS, 1]);
+
+        let large = vec![doc! {"u": {"$set": {"motd": "x".repeat(4 * 1024 * 1024)}}}; 9];
+        let sizes: Vec<usize> = command_chunks(&large).iter().map(|c| c.len()).collect();
+        assert_eq!(sizes, vec![3, 3, 3]);
+
+        assert!(command_chunks(&[]).is_empty());
+    }
+}
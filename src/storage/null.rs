@@ -0,0 +1,72 @@
+use async_trait::async_trait;
+
+use crate::model::{
+    failure::ProbeFailure, mods::ModInstall, observation::ServerObservation, session::PlayerSession,
+};
+
+use super::{
+    HistoryQuery, OnlineCount, PlayerCount, PlayerFilter, PlayerRow, ScheduledProbe, ServerFilter,
+    ServerRow, ServerState, Storage, StorageResult, Uptime, VersionCount, WatchTarget,
+};
+
+/// Discards everything, for runs that only write to an output sink.
+pub struct NullStorage;
+
+#[async_trait]
+impl Storage for NullStorage {
+    async fn record_server(&self, _observation: &ServerObservation) -> StorageResult<()> {
+        Ok(())
+    }
+
+    async fn record_failure(&self, _failure: &ProbeFailure) -> StorageResult<()> {
+        Ok(())
+    }
+
+    async fn record_players(&self, _observation: &ServerObservation) -> StorageResult<()> {
+        Ok(())
+    }
+
+    async fn schedule_probes(&self, _probes: &[ScheduledProbe]) -> StorageResult<()> {
+        Ok(())
+    }
+
+    async fn find_mods(&self, _mod_id: &str) -> StorageResult<Vec<ModInstall>> {
+        Ok(Vec::new())
+    }
+
+    async fn version_counts(&self) -> StorageResult<Vec<VersionCount>> {
+        Ok(Vec::new())
+    }
+
+    async fn online_counts(&self) -> StorageResult<Vec<OnlineCount>> {
+        Ok(Vec::new())
+    }
+
+    async fn watch_targets(&self) -> StorageResult<Vec<WatchTarget>> {
+        Ok(Vec::new())
+    }
+
+    async fn server_state(&self, _host: &str, _port: i16) -> StorageResult<Option<ServerState>> {
+        Ok(None)
+    }
+
+    async fn player_counts(&self, _query: &HistoryQuery) -> StorageResult<Vec<PlayerCount>> {
+        Ok(Vec::new())
+    }
+
+    async fn uptime(&self, _query: &HistoryQuery) -> StorageResult<Uptime> {
+        Ok(Uptime::default())
+    }
+
+    async fn player_sessions(&self, _uuid: &str) -> StorageResult<Vec<PlayerSession>> {
+        Ok(Vec::new())
+    }
+
+    async fn export_servers(&self, _filter: &ServerFilter) -> StorageResult<Vec<ServerRow>> {
+        Ok(Vec::new())
+    }
+
+    async fn export_players(&self, _filter: &PlayerFilter) -> StorageResult<Vec<PlayerRow>> {
+        Ok(Vec::new())
+    }
+}
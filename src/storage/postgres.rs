@@ -0,0 +1,1248 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use deadpool_postgres::{Config, GenericClient, Pool, Runtime};
+use mongodb::bson::DateTime;
+use serde_json::Value;
+use tokio_postgres::NoTls;
+
+use crate::model::{
+    failure::ProbeFailure,
+    history::HistoryEntry,
+    mods::ModInstall,
+    observation::{content_hash, ServerObservation},
+    server::ServerStatus,
+    session::{OpenSession, PlayerSession, SessionChanges},
+    uuid::UUID,
+};
+
+use super::{
+    HistoryQuery, OnlineCount, PendingWrite, PlayerCount, PlayerFilter, PlayerRow, ScheduledProbe,
+    ServerFilter, ServerRow, ServerState, Storage, StorageError, StorageResult, Uptime,
+    VersionCount, WatchTarget,
+};
+
+/// Schema changes, applied in order and recorded in `schema_migrations`, so
+/// only ever append to this list.
+const MIGRATIONS: [&str; 7] = [
+    r#"
+CREATE TABLE favicons (
+    hash TEXT PRIMARY KEY,
+    data TEXT NOT NULL,
+    first_seen TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE servers (
+    id BIGSERIAL PRIMARY KEY,
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    whitelist BOOLEAN NOT NULL DEFAULT FALSE,
+    motd TEXT NOT NULL,
+    favicon_hash TEXT REFERENCES favicons (hash),
+    version_name TEXT NOT NULL,
+    protocol INTEGER NOT NULL,
+    max_players INTEGER NOT NULL,
+    online_players INTEGER NOT NULL,
+    forge BOOLEAN NOT NULL,
+    mods_truncated BOOLEAN NOT NULL,
+    forge_channels JSONB NOT NULL,
+    enforces_secure_chat BOOLEAN NOT NULL,
+    previews_chat BOOLEAN,
+    prevents_chat_reports BOOLEAN,
+    is_modded BOOLEAN,
+    mod_info_type TEXT,
+    extensions JSONB NOT NULL,
+    software TEXT NOT NULL,
+    software_confidence DOUBLE PRECISION NOT NULL,
+    resolved_version JSONB NOT NULL,
+    parse_warnings TEXT[] NOT NULL,
+    first_seen TIMESTAMPTZ NOT NULL,
+    last_updated TIMESTAMPTZ NOT NULL,
+    UNIQUE (host, port)
+);
+CREATE INDEX servers_protocol ON servers (protocol);
+
+CREATE TABLE observations (
+    id BIGSERIAL PRIMARY KEY,
+    server_id BIGINT NOT NULL REFERENCES servers (id),
+    observed_at TIMESTAMPTZ NOT NULL,
+    online_players INTEGER NOT NULL,
+    max_players INTEGER NOT NULL,
+    protocol INTEGER NOT NULL,
+    version_name TEXT NOT NULL,
+    motd_hash TEXT NOT NULL
+);
+CREATE INDEX observations_server ON observations (server_id, observed_at);
+
+CREATE TABLE players (
+    uuid TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    last_seen TIMESTAMPTZ NOT NULL,
+    last_updated TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE server_players (
+    server_id BIGINT NOT NULL REFERENCES servers (id),
+    uuid TEXT NOT NULL,
+    first_seen TIMESTAMPTZ NOT NULL,
+    last_seen TIMESTAMPTZ NOT NULL,
+    times_seen INTEGER NOT NULL DEFAULT 1,
+    PRIMARY KEY (server_id, uuid)
+);
+CREATE INDEX server_players_uuid ON server_players (uuid);
+
+CREATE TABLE mods (
+    server_id BIGINT NOT NULL REFERENCES servers (id),
+    mod_id TEXT NOT NULL,
+    version TEXT NOT NULL,
+    first_seen TIMESTAMPTZ NOT NULL,
+    last_seen TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (server_id, mod_id)
+);
+CREATE INDEX mods_mod_id ON mods (mod_id);
+
+CREATE TABLE raw_responses (
+    server_id BIGINT NOT NULL REFERENCES servers (id),
+    received TIMESTAMPTZ NOT NULL,
+    status TEXT NOT NULL
+);
+"#,
+    // History of offline probes too, with latency
+    r#"
+ALTER TABLE observations
+    ADD COLUMN online BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD COLUMN latency_ms INTEGER,
+    ALTER COLUMN online_players DROP NOT NULL,
+    ALTER COLUMN max_players DROP NOT NULL,
+    ALTER COLUMN protocol DROP NOT NULL,
+    ALTER COLUMN version_name DROP NOT NULL,
+    ALTER COLUMN motd_hash DROP NOT NULL;
+ALTER TABLE observations ALTER COLUMN online DROP DEFAULT;
+"#,
+    r#"
+CREATE TABLE player_sessions (
+    id BIGSERIAL PRIMARY KEY,
+    server_id BIGINT NOT NULL REFERENCES servers (id),
+    uuid TEXT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    last_seen TIMESTAMPTZ NOT NULL,
+    ended_at TIMESTAMPTZ
+);
+CREATE UNIQUE INDEX player_sessions_open ON player_sessions (server_id, uuid)
+    WHERE ended_at IS NULL;
+CREATE INDEX player_sessions_uuid ON player_sessions (uuid, started_at);
+"#,
+    r#"
+ALTER TABLE servers
+    ADD COLUMN last_error TEXT,
+    ADD COLUMN last_error_message TEXT,
+    ADD COLUMN last_error_at TIMESTAMPTZ,
+    ADD COLUMN consecutive_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE observations ADD COLUMN error TEXT;
+"#,
+    r#"
+ALTER TABLE servers
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'online',
+    ADD COLUMN status_since TIMESTAMPTZ;
+UPDATE servers SET status = 'unreachable' WHERE consecutive_failures > 0;
+UPDATE servers SET status_since = COALESCE(last_error_at, last_updated);
+CREATE INDEX servers_status ON servers (status);
+"#,
+    r#"
+ALTER TABLE servers ADD COLUMN next_probe_at TIMESTAMPTZ;
+"#,
+    r#"
+CREATE INDEX players_name ON players (lower(name));
+"#,
+];
+
+/// Normalized backend for analytics, see `MIGRATIONS` for the schema.
+///
+/// A batch of writes takes one multi-row statement per table, by unnesting
+/// array parameters, rather than a few per server.
+#[derive(Clone)]
+pub struct PostgresStorage {
+    pool: Pool,
+    /// Consecutive failures after which a server is marked gone
+    gone_after: i32,
+    /// Time without a sighting after which a player's session is closed
+    session_gap: Duration,
+}
+
+impl PostgresStorage {
+    pub async fn connect(url: &str, gone_after: i32, session_gap: Duration) -> StorageResult<Self> {
+        let config = Config {
+            url: Some(url.to_owned()),
+            ..Default::default()
+        };
+        let pool = config
+            .create_pool(Some(Runtime::Tokio1), NoTls)
+            .map_err(|err| StorageError::PostgresPool(err.to_string()))?;
+
+        let storage = Self {
+            pool,
+            gone_after,
+            session_gap,
+        };
+        storage.migrate().await?;
+        Ok(storage)
+    }
+
+    async fn migrate(&self) -> StorageResult<()> {
+        let mut client = self.client().await?;
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER PRIMARY KEY)",
+            )
+            .await?;
+
+        let applied: i32 = client
+            .query_one(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+                &[],
+            )
+            .await?
+            .get(0);
+
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+            let tx = client.transaction().await?;
+            tx.batch_execute(migration).await?;
+            tx.execute(
+                "INSERT INTO schema_migrations (version) VALUES ($1)",
+                &[&(version as i32 + 1)],
+            )
+            .await?;
+            tx.commit().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn client(&self) -> StorageResult<deadpool_postgres::Client> {
+        self.pool
+            .get()
+            .await
+            .map_err(|err| StorageError::PostgresPool(err.to_string()))
+    }
+}
+
+fn timestamp(date_time: DateTime) -> SystemTime {
+    date_time.to_system_time()
+}
+
+fn json<T: serde::Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap()
+}
+
+fn status(s: &str) -> ServerStatus {
+    ServerStatus::parse(s).unwrap_or_default()
+}
+
+/// Splits writes into rounds that touch each server at most once, keeping the
+/// order of writes to the same server, as a multi-row upsert can't change a
+/// row twice. Schedules are left out, they're written after everything else.
+fn rounds(batch: &[PendingWrite]) -> Vec<Vec<&PendingWrite>> {
+    let mut rounds: Vec<Vec<&PendingWrite>> = Vec::new();
+    let mut next_round: HashMap<(&str, i16), usize> = HashMap::new();
+
+    for write in batch {
+        if matches!(write, PendingWrite::Schedule(_)) {
+            continue;
+        }
+        let round = next_round.entry(write.server()).or_default();
+        if *round == rounds.len() {
+            rounds.push(Vec::new());
+        }
+        rounds[*round].push(write);
+        *round += 1;
+    }
+    rounds
+}
+
+/// One array parameter for an `UNNEST`, holding a field of every row.
+fn column<'a, T: ?Sized, U>(rows: &[&'a T], field: impl Fn(&'a T) -> U) -> Vec<U> {
+    rows.iter().map(|row| field(row)).collect()
+}
+
+/// Writes observations of distinct servers, with one statement per table for
+/// all of them.
+async fn write_servers(
+    tx: &impl GenericClient,
+    observations: &[&ServerObservation],
+    session_gap: Duration,
+) -> StorageResult<()> {
+    if observations.is_empty() {
+        return Ok(());
+    }
+    let observed_at = column(observations, |o| timestamp(o.observed_at));
+
+    let favicon_hashes = column(observations, |o| o.favicon.as_deref().map(content_hash));
+    let with_favicon: Vec<usize> = (0..observations.len())
+        .filter(|&i| observations[i].favicon.is_some())
+        .collect();
+    tx.execute(
+        "INSERT INTO favicons (hash, data, first_seen)
+        SELECT DISTINCT ON (hash) hash, data, first_seen
+        FROM UNNEST($1::TEXT[], $2::TEXT[], $3::TIMESTAMPTZ[]) AS f (hash, data, first_seen)
+        ON CONFLICT (hash) DO NOTHING",
+        &[
+            &with_favicon
+                .iter()
+                .map(|&i| favicon_hashes[i].as_deref())
+                .collect::<Vec<_>>(),
+            &with_favicon
+                .iter()
+                .map(|&i| observations[i].favicon.as_deref())
+                .collect::<Vec<_>>(),
+            &with_favicon
+                .iter()
+                .map(|&i| observed_at[i])
+                .collect::<Vec<_>>(),
+        ],
+    )
+    .await?;
+
+    let server_ids: HashMap<(String, i32), i64> = tx
+        .query(
+            "INSERT INTO servers (
+                host, port, motd, favicon_hash, version_name, protocol, max_players,
+                online_players, forge, mods_truncated, forge_channels, enforces_secure_chat,
+                previews_chat, prevents_chat_reports, is_modded, mod_info_type, extensions,
+                software, software_confidence, resolved_version, parse_warnings, first_seen,
+                last_updated, status_since
+            )
+            SELECT host, port, motd, favicon_hash, version_name, protocol, max_players,
+                online_players, forge, mods_truncated, forge_channels, enforces_secure_chat,
+                previews_chat, prevents_chat_reports, is_modded, mod_info_type, extensions,
+                software, software_confidence, resolved_version,
+                ARRAY(SELECT jsonb_array_elements_text(parse_warnings)),
+                observed_at, observed_at, observed_at
+            FROM UNNEST(
+                $1::TEXT[], $2::INTEGER[], $3::TEXT[], $4::TEXT[], $5::TEXT[], $6::INTEGER[],
+                $7::INTEGER[], $8::INTEGER[], $9::BOOLEAN[], $10::BOOLEAN[], $11::JSONB[],
+                $12::BOOLEAN[], $13::BOOLEAN[], $14::BOOLEAN[], $15::BOOLEAN[], $16::TEXT[],
+                $17::JSONB[], $18::TEXT[], $19::DOUBLE PRECISION[], $20::JSONB[], $21::JSONB[],
+                $22::TIMESTAMPTZ[]
+            ) AS s (
+                host, port, motd, favicon_hash, version_name, protocol, max_players,
+                online_players, forge, mods_truncated, forge_channels, enforces_secure_chat,
+                previews_chat, prevents_chat_reports, is_modded, mod_info_type, extensions,
+                software, software_confidence, resolved_version, parse_warnings, observed_at
+            )
+            ON CONFLICT (host, port) DO UPDATE SET
+                motd = EXCLUDED.motd,
+                favicon_hash = EXCLUDED.favicon_hash,
+                version_name = EXCLUDED.version_name,
+                protocol = EXCLUDED.protocol,
+                max_players = EXCLUDED.max_players,
+                online_players = EXCLUDED.online_players,
+                forge = EXCLUDED.forge,
+                mods_truncated = EXCLUDED.mods_truncated,
+                forge_channels = EXCLUDED.forge_channels,
+                enforces_secure_chat = EXCLUDED.enforces_secure_chat,
+                previews_chat = EXCLUDED.previews_chat,
+                prevents_chat_reports = EXCLUDED.prevents_chat_reports,
+                is_modded = EXCLUDED.is_modded,
+                mod_info_type = EXCLUDED.mod_info_type,
+                extensions = EXCLUDED.extensions,
+                software = EXCLUDED.software,
+                software_confidence = EXCLUDED.software_confidence,
+                resolved_version = EXCLUDED.resolved_version,
+                parse_warnings = EXCLUDED.parse_warnings,
+                last_updated = EXCLUDED.last_updated,
+                consecutive_failures = 0,
+                status_since = CASE WHEN servers.status = 'online' THEN servers.status_since
+                    ELSE EXCLUDED.status_since END,
+                status = 'online'
+            RETURNING host, port, id",
+            &[
+                &column(observations, |o| o.host.as_str()),
+                &column(observations, |o| o.port as i32),
+                &column(observations, |o| o.motd.as_str()),
+                &favicon_hashes,
+                &column(observations, |o| o.version.name.as_str()),
+                &column(observations, |o| o.version.protocol),
+                &column(observations, |o| o.online.max),
+                &column(observations, |o| o.online.players),
+                &column(observations, |o| o.forge),
+                &column(observations, |o| o.mods_truncated),
+                &column(observations, |o| json(&o.forge_channels)),
+                &column(observations, |o| o.enforces_secure_chat),
+                &column(observations, |o| o.previews_chat),
+                &column(observations, |o| o.prevents_chat_reports),
+                &column(observations, |o| o.is_modded),
+                &column(observations, |o| o.mod_info_type.as_deref()),
+                &column(observations, |o| json(&o.extensions)),
+                &column(observations, |o| format!("{:?}", o.software.software)),
+                &column(observations, |o| o.software.confidence),
+                &column(observations, |o| json(&o.resolved_version)),
+                &column(observations, |o| json(&o.parse_warnings)),
+                &observed_at,
+            ],
+        )
+        .await?
+        .iter()
+        .map(|row| ((row.get(0), row.get(1)), row.get(2)))
+        .collect();
+    let ids = column(observations, |o| {
+        server_ids[&(o.host.clone(), o.port as i32)]
+    });
+
+    let entries: Vec<HistoryEntry> = observations
+        .iter()
+        .map(|o| HistoryEntry::online(o))
+        .collect();
+    let entries: Vec<&HistoryEntry> = entries.iter().collect();
+    tx.execute(
+        "INSERT INTO observations (
+            server_id, observed_at, online, online_players, max_players, latency_ms,
+            protocol, version_name, motd_hash
+        )
+        SELECT server_id, observed_at, TRUE, online_players, max_players, latency_ms,
+            protocol, version_name, motd_hash
+        FROM UNNEST(
+            $1::BIGINT[], $2::TIMESTAMPTZ[], $3::INTEGER[], $4::INTEGER[], $5::INTEGER[],
+            $6::INTEGER[], $7::TEXT[], $8::TEXT[]
+        ) AS o (
+            server_id, observed_at, online_players, max_players, latency_ms, protocol,
+            version_name, motd_hash
+        )",
+        &[
+            &ids,
+            &observed_at,
+            &column(&entries, |e| e.online_players),
+            &column(&entries, |e| e.max_players),
+            &column(&entries, |e| e.latency_ms),
+            &column(&entries, |e| e.protocol),
+            &column(&entries, |e| e.version_name.as_deref()),
+            &column(&entries, |e| e.motd_hash.as_deref()),
+        ],
+    )
+    .await?;
+
+    let mut open: HashMap<i64, HashMap<UUID, OpenSession>> = HashMap::new();
+    for row in tx
+        .query(
+            "SELECT server_id, uuid, started_at, last_seen FROM player_sessions
+            WHERE server_id = ANY($1) AND ended_at IS NULL",
+            &[&ids],
+        )
+        .await?
+    {
+        open.entry(row.get(0)).or_default().insert(
+            UUID(row.get(1)),
+            OpenSession {
+                started_at: DateTime::from_system_time(row.get(2)),
+                last_seen: DateTime::from_system_time(row.get(3)),
+            },
+        );
+    }
+
+    let (mut ended_servers, mut ended) = (Vec::new(), Vec::new());
+    let (mut session_servers, mut uuids, mut started, mut last_seen) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (i, o) in observations.iter().enumerate() {
+        let open = open.remove(&ids[i]).unwrap_or_default();
+        let changes = SessionChanges::new(&open, o, session_gap);
+        for uuid in changes.ended {
+            ended_servers.push(ids[i]);
+            ended.push(uuid.0);
+        }
+        for player in changes.online {
+            session_servers.push(ids[i]);
+            uuids.push(player.uuid.0);
+            started.push(timestamp(player.online_since));
+            last_seen.push(observed_at[i]);
+        }
+    }
+    // Ended sessions go first, a player back after the gap gets a new one
+    tx.execute(
+        "UPDATE player_sessions ps SET ended_at = ps.last_seen
+        FROM UNNEST($1::BIGINT[], $2::TEXT[]) AS e (server_id, uuid)
+        WHERE ps.server_id = e.server_id AND ps.uuid = e.uuid AND ps.ended_at IS NULL",
+        &[&ended_servers, &ended],
+    )
+    .await?;
+    tx.execute(
+        "INSERT INTO player_sessions (server_id, uuid, started_at, last_seen)
+        SELECT * FROM UNNEST($1::BIGINT[], $2::TEXT[], $3::TIMESTAMPTZ[], $4::TIMESTAMPTZ[])
+        ON CONFLICT (server_id, uuid) WHERE ended_at IS NULL DO UPDATE SET
+            last_seen = EXCLUDED.last_seen",
+        &[&session_servers, &uuids, &started, &last_seen],
+    )
+    .await?;
+
+    let (mut player_servers, mut uuids, mut seen) = (Vec::new(), Vec::new(), Vec::new());
+    for (i, o) in observations.iter().enumerate() {
+        for player in o.known_online() {
+            player_servers.push(ids[i]);
+            uuids.push(player.uuid.0.as_str());
+            seen.push(observed_at[i]);
+        }
+    }
+    tx.execute(
+        "INSERT INTO server_players (server_id, uuid, first_seen, last_seen)
+        SELECT server_id, uuid, seen, seen
+        FROM UNNEST($1::BIGINT[], $2::TEXT[], $3::TIMESTAMPTZ[]) AS p (server_id, uuid, seen)
+        ON CONFLICT (server_id, uuid) DO UPDATE SET
+            last_seen = EXCLUDED.last_seen,
+            times_seen = server_players.times_seen + 1",
+        &[&player_servers, &uuids, &seen],
+    )
+    .await?;
+
+    let (mut mod_servers, mut mod_ids, mut versions, mut seen) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (i, o) in observations.iter().enumerate() {
+        for server_mod in &o.mods {
+            mod_servers.push(ids[i]);
+            mod_ids.push(server_mod.mod_id.as_str());
+            versions.push(server_mod.version.as_str());
+            seen.push(observed_at[i]);
+        }
+    }
+    tx.execute(
+        "INSERT INTO mods (server_id, mod_id, version, first_seen, last_seen)
+        SELECT DISTINCT ON (server_id, mod_id) server_id, mod_id, version, seen, seen
+        FROM UNNEST($1::BIGINT[], $2::TEXT[], $3::TEXT[], $4::TIMESTAMPTZ[])
+            AS m (server_id, mod_id, version, seen)
+        ON CONFLICT (server_id, mod_id) DO UPDATE SET
+            version = EXCLUDED.version,
+            last_seen = EXCLUDED.last_seen",
+        &[&mod_servers, &mod_ids, &versions, &seen],
+    )
+    .await?;
+    // A truncated list doesn't say which mods are gone
+    let complete: Vec<usize> = (0..observations.len())
+        .filter(|&i| !observations[i].mods_truncated)
+        .collect();
+    tx.execute(
+        "DELETE FROM mods m USING UNNEST($1::BIGINT[], $2::TIMESTAMPTZ[]) AS s (server_id, seen)
+        WHERE m.server_id = s.server_id AND m.last_seen < s.seen",
+        &[
+            &complete.iter().map(|&i| ids[i]).collect::<Vec<_>>(),
+            &complete.iter().map(|&i| observed_at[i]).collect::<Vec<_>>(),
+        ],
+    )
+    .await?;
+
+    let raw: Vec<usize> = (0..observations.len())
+        .filter(|&i| observations[i].raw.is_some())
+        .collect();
+    tx.execute(
+        "INSERT INTO raw_responses (server_id, received, status)
+        SELECT * FROM UNNEST($1::BIGINT[], $2::TIMESTAMPTZ[], $3::TEXT[])",
+        &[
+            &raw.iter().map(|&i| ids[i]).collect::<Vec<_>>(),
+            &raw.iter().map(|&i| observed_at[i]).collect::<Vec<_>>(),
+            &raw.iter()
+                .map(|&i| observations[i].raw.as_deref())
+                .collect::<Vec<_>>(),
+        ],
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Last error, offline history entry and closed sessions, for the servers we
+/// know about out of failures of distinct servers.
+async fn write_failures(
+    tx: &impl GenericClient,
+    failures: &[&ProbeFailure],
+    gone_after: i32,
+) -> StorageResult<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    // Every expression sees the row as it was before the update
+    let rows = tx
+        .query(
+            "UPDATE servers s SET
+                last_error = f.error,
+                last_error_message = f.message,
+                last_error_at = f.observed_at,
+                consecutive_failures = s.consecutive_failures + 1,
+                status = CASE WHEN s.consecutive_failures + 1 >= $7 THEN 'gone'
+                    ELSE f.status END,
+                status_since = CASE
+                    WHEN s.status = CASE WHEN s.consecutive_failures + 1 >= $7 THEN 'gone'
+                        ELSE f.status END
+                    THEN s.status_since ELSE f.observed_at END
+            FROM UNNEST(
+                $1::TEXT[], $2::INTEGER[], $3::TEXT[], $4::TEXT[], $5::TIMESTAMPTZ[], $6::TEXT[]
+            ) AS f (host, port, error, message, observed_at, status)
+            WHERE s.host = f.host AND s.port = f.port
+            RETURNING s.id, f.observed_at, f.error",
+            &[
+                &column(failures, |f| f.host.as_str()),
+                &column(failures, |f| f.port as i32),
+                &column(failures, |f| f.error.as_str()),
+                &column(failures, |f| f.message.as_str()),
+                &column(failures, |f| timestamp(f.observed_at)),
+                &column(failures, |f| f.status.as_str()),
+                &gone_after,
+            ],
+        )
+        .await?;
+    let ids: Vec<i64> = rows.iter().map(|row| row.get(0)).collect();
+    let observed_at: Vec<SystemTime> = rows.iter().map(|row| row.get(1)).collect();
+    let errors: Vec<&str> = rows.iter().map(|row| row.get(2)).collect();
+
+    tx.execute(
+        "INSERT INTO observations (server_id, observed_at, online, error)
+        SELECT server_id, observed_at, FALSE, error
+        FROM UNNEST($1::BIGINT[], $2::TIMESTAMPTZ[], $3::TEXT[])
+            AS f (server_id, observed_at, error)",
+        &[&ids, &observed_at, &errors],
+    )
+    .await?;
+    tx.execute(
+        "UPDATE player_sessions SET ended_at = last_seen
+        WHERE server_id = ANY($1) AND ended_at IS NULL",
+        &[&ids],
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn write_schedules(
+    client: &impl GenericClient,
+    probes: &[&ScheduledProbe],
+) -> StorageResult<()> {
+    if probes.is_empty() {
+        return Ok(());
+    }
+
+    let hosts: Vec<&str> = probes.iter().map(|p| p.host.as_str()).collect();
+    let ports: Vec<i32> = probes.iter().map(|p| p.port as i32).collect();
+    let times: Vec<SystemTime> = probes.iter().map(|p| timestamp(p.next_probe_at)).collect();
+    client
+        .execute(
+            "UPDATE servers s SET next_probe_at = p.next_probe_at
+            FROM UNNEST($1::TEXT[], $2::INTEGER[], $3::TIMESTAMPTZ[])
+                AS p (host, port, next_probe_at)
+            WHERE s.host = p.host AND s.port = p.port",
+            &[&hosts, &ports, &times],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Upserts the sampled players of every observation, with the name from the
+/// latest one for players seen more than once.
+async fn write_players(
+    client: &impl GenericClient,
+    observations: &[&ServerObservation],
+) -> StorageResult<()> {
+    let (mut uuids, mut names, mut seen) = (Vec::new(), Vec::new(), Vec::new());
+    for o in observations {
+        for player in o.known_players() {
+            uuids.push(player.id.0.as_str());
+            names.push(player.name.as_str());
+            seen.push(timestamp(o.observed_at));
+        }
+    }
+    if uuids.is_empty() {
+        return Ok(());
+    }
+
+    client
+        .execute(
+            "INSERT INTO players (uuid, name, last_seen, last_updated)
+            SELECT DISTINCT ON (uuid) uuid, name, seen, $4
+            FROM UNNEST($1::TEXT[], $2::TEXT[], $3::TIMESTAMPTZ[]) AS p (uuid, name, seen)
+            ORDER BY uuid, seen DESC
+            ON CONFLICT (uuid) DO UPDATE SET
+                name = EXCLUDED.name,
+                last_seen = EXCLUDED.last_seen,
+                last_updated = EXCLUDED.last_updated",
+            &[&uuids, &names, &seen, &SystemTime::now()],
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn record_server(&self, observation: &ServerObservation) -> StorageResult<()> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        write_servers(&tx, &[observation], self.session_gap).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn record_failure(&self, failure: &ProbeFailure) -> StorageResult<()> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        write_failures(&tx, &[failure], self.gone_after).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn record_players(&self, observation: &ServerObservation) -> StorageResult<()> {
+        let client = self.client().await?;
+        write_players(&client, &[observation]).await
+    }
+
+    async fn schedule_probes(&self, probes: &[ScheduledProbe]) -> StorageResult<()> {
+        let client = self.client().await?;
+        write_schedules(&client, &probes.iter().collect::<Vec<_>>()).await
+    }
+
+    async fn record_batch(&self, batch: &[PendingWrite]) -> StorageResult<()> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+
+        let mut sampled = Vec::new();
+        for round in rounds(batch) {
+            let (mut observations, mut failures) = (Vec::new(), Vec::new());
+            for write in round {
+                match write {
+                    PendingWrite::Observation(observation) => observations.push(&**observation),
+                    PendingWrite::Failure(failure) => failures.push(failure),
+                    PendingWrite::Schedule(_) => {}
+                }
+            }
+            write_servers(&tx, &observations, self.session_gap).await?;
+            write_failures(&tx, &failures, self.gone_after).await?;
+            sampled.extend(observations);
+        }
+        write_players(&tx, &sampled).await?;
+
+        let schedules: Vec<&ScheduledProbe> = batch
+            .iter()
+            .filter_map(|write| match write {
+                PendingWrite::Schedule(probe) => Some(probe),
+                _ => None,
+            })
+            .collect();
+        write_schedules(&tx, &schedules).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn find_mods(&self, mod_id: &str) -> StorageResult<Vec<ModInstall>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT m.mod_id, m.version, s.host, s.port, m.first_seen, m.last_seen
+                FROM mods m JOIN servers s ON s.id = m.server_id
+                WHERE m.mod_id = $1",
+                &[&mod_id],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| ModInstall {
+                mod_id: row.get(0),
+                version: row.get(1),
+                host: row.get(2),
+                port: row.get::<_, i32>(3) as i16,
+                first_seen: DateTime::from_system_time(row.get(4)),
+                last_seen: DateTime::from_system_time(row.get(5)),
+            })
+            .collect())
+    }
+
+    async fn version_counts(&self) -> StorageResult<Vec<VersionCount>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT protocol, COUNT(*),
+                    COUNT(*) FILTER (WHERE (resolved_version ->> 'mismatch')::BOOLEAN)
+                FROM servers GROUP BY protocol ORDER BY COUNT(*) DESC",
+                &[],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| VersionCount {
+                protocol: row.get(0),
+                servers: row.get(1),
+                mismatched: row.get(2),
+            })
+            .collect())
+    }
+
+    async fn online_counts(&self) -> StorageResult<Vec<OnlineCount>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT protocol, COUNT(*), COALESCE(SUM(online_players), 0)::BIGINT
+                FROM servers WHERE status = 'online' GROUP BY protocol",
+                &[],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| OnlineCount {
+                protocol: row.get(0),
+                servers: row.get(1),
+                players: row.get(2),
+            })
+            .collect())
+    }
+
+    async fn watch_targets(&self) -> StorageResult<Vec<WatchTarget>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT host, port, status, consecutive_failures, online_players, next_probe_at
+                FROM servers",
+                &[],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| WatchTarget {
+                host: row.get(0),
+                port: row.get::<_, i32>(1) as i16,
+                status: status(row.get(2)),
+                consecutive_failures: row.get(3),
+                online_players: row.get(4),
+                next_probe_at: row
+                    .get::<_, Option<SystemTime>>(5)
+                    .map(DateTime::from_system_time),
+            })
+            .collect())
+    }
+
+    async fn server_state(&self, host: &str, port: i16) -> StorageResult<Option<ServerState>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT status, status_since, consecutive_failures, last_error, last_error_at
+                FROM servers WHERE host = $1 AND port = $2",
+                &[&host, &(port as i32)],
+            )
+            .await?;
+
+        Ok(row.map(|row| ServerState {
+            status: status(row.get(0)),
+            status_since: row
+                .get::<_, Option<SystemTime>>(1)
+                .map(DateTime::from_system_time),
+            consecutive_failures: row.get(2),
+            last_error: row.get(3),
+            last_error_at: row
+                .get::<_, Option<SystemTime>>(4)
+                .map(DateTime::from_system_time),
+        }))
+    }
+
+    async fn player_counts(&self, query: &HistoryQuery) -> StorageResult<Vec<PlayerCount>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT o.observed_at, o.online_players, o.max_players
+                FROM observations o JOIN servers s ON s.id = o.server_id
+                WHERE s.host = $1 AND s.port = $2 AND o.online
+                    AND ($3::TIMESTAMPTZ IS NULL OR o.observed_at >= $3)
+                    AND ($4::TIMESTAMPTZ IS NULL OR o.observed_at < $4)
+                ORDER BY o.observed_at",
+                &[
+                    &query.host,
+                    &(query.port as i32),
+                    &query.since.map(timestamp),
+                    &query.until.map(timestamp),
+                ],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| PlayerCount {
+                observed_at: DateTime::from_system_time(row.get(0)),
+                online_players: row.get(1),
+                max_players: row.get(2),
+            })
+            .collect())
+    }
+
+    async fn uptime(&self, query: &HistoryQuery) -> StorageResult<Uptime> {
+        let client = self.client().await?;
+        let row = client
+            .query_one(
+                "SELECT COUNT(*), COUNT(*) FILTER (WHERE o.online)
+                FROM observations o JOIN servers s ON s.id = o.server_id
+                WHERE s.host = $1 AND s.port = $2
+                    AND ($3::TIMESTAMPTZ IS NULL OR o.observed_at >= $3)
+                    AND ($4::TIMESTAMPTZ IS NULL OR o.observed_at < $4)",
+                &[
+                    &query.host,
+                    &(query.port as i32),
+                    &query.since.map(timestamp),
+                    &query.until.map(timestamp),
+                ],
+            )
+            .await?;
+
+        Ok(Uptime {
+            probes: row.get(0),
+            online: row.get(1),
+        })
+    }
+
+    async fn player_sessions(&self, uuid: &str) -> StorageResult<Vec<PlayerSession>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT p.uuid, s.host, s.port, p.started_at, p.last_seen, p.ended_at
+                FROM player_sessions p JOIN servers s ON s.id = p.server_id
+                WHERE p.uuid = $1 ORDER BY p.started_at DESC",
+                &[&uuid],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| PlayerSession {
+                uuid: UUID(row.get(0)),
+                host: row.get(1),
+                port: row.get::<_, i32>(2) as i16,
+                started_at: DateTime::from_system_time(row.get(3)),
+                last_seen: DateTime::from_system_time(row.get(4)),
+                ended_at: row
+                    .get::<_, Option<SystemTime>>(5)
+                    .map(DateTime::from_system_time),
+            })
+            .collect())
+    }
+
+    async fn export_servers(&self, filter: &ServerFilter) -> StorageResult<Vec<ServerRow>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT s.host, s.port, s.motd, s.version_name, s.protocol, s.online_players,
+                    s.max_players, s.software, s.forge,
+                    COALESCE((SELECT string_agg(m.mod_id || '@' || m.version, ';')
+                        FROM mods m WHERE m.server_id = s.id), ''),
+                    s.last_updated, s.status
+                FROM servers s
+                WHERE ($1::INTEGER IS NULL OR s.protocol = $1)
+                    AND ($2::TIMESTAMPTZ IS NULL OR s.last_updated >= $2)
+                    AND ($3::TIMESTAMPTZ IS NULL OR s.last_updated < $3)
+                    AND ($4::INTEGER IS NULL OR s.online_players >= $4)
+                    AND ($5::TEXT IS NULL OR s.status = $5)
+                    AND ($6::TEXT IS NULL OR s.host = $6)
+                    AND ($7::INTEGER IS NULL OR s.port = $7)
+                    AND ($8::INTEGER IS NULL OR s.online_players <= $8)
+                    AND ($9::TEXT IS NULL OR lower(s.software) = lower($9))
+                    AND ($10::TEXT IS NULL OR EXISTS (SELECT 1 FROM mods m
+                        WHERE m.server_id = s.id AND m.mod_id = $10))
+                    AND ($11::TEXT IS NULL OR strpos(lower(s.motd), lower($11)) > 0)
+                ORDER BY s.online_players DESC, s.host, s.port
+                LIMIT $12::BIGINT OFFSET $13::BIGINT",
+                &[
+                    &filter.protocol,
+                    &filter.updated_after.map(timestamp),
+                    &filter.updated_before.map(timestamp),
+                    &filter.min_players,
+                    &filter.status.map(|status| status.as_str()),
+                    &filter.host,
+                    &filter.port.map(i32::from),
+                    &filter.max_players,
+                    &filter.software,
+                    &filter.mod_id,
+                    &filter.motd,
+                    &filter.limit,
+                    &filter.offset.unwrap_or(0),
+                ],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| ServerRow {
+                host: row.get(0),
+                port: row.get::<_, i32>(1) as i16,
+                motd: row.get(2),
+                version_name: row.get(3),
+                protocol: row.get(4),
+                online_players: row.get(5),
+                max_players: row.get(6),
+                software: row.get(7),
+                forge: row.get(8),
+                mods: row.get(9),
+                last_updated: DateTime::from_system_time(row.get(10)),
+                status: status(row.get(11)),
+            })
+            .collect())
+    }
+
+    async fn export_players(&self, filter: &PlayerFilter) -> StorageResult<Vec<PlayerRow>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT uuid, name, last_seen, last_updated FROM players
+                WHERE ($1::TIMESTAMPTZ IS NULL OR last_seen >= $1)
+                    AND ($2::TIMESTAMPTZ IS NULL OR last_seen < $2)
+                    AND ($3::TEXT IS NULL OR uuid = $3)
+                    AND ($4::TEXT IS NULL OR lower(name) = lower($4))",
+                &[
+                    &filter.seen_after.map(timestamp),
+                    &filter.seen_before.map(timestamp),
+                    &filter.uuid,
+                    &filter.name,
+                ],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| PlayerRow {
+                uuid: row.get(0),
+                name: row.get(1),
+                last_seen: DateTime::from_system_time(row.get(2)),
+                last_updated: DateTime::from_system_time(row.get(3)),
+            })
+            .collect())
+    }
+}
+
+/// Runs against the database in `TEST_DATABASE_URL`, with
+/// `cargo test -- --ignored`. Every test writes under its own host so they can
+/// share one database.
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use serde_json::json;
+
+    use crate::model::mods::ServerMod;
+    use crate::response::{Response, ResponseData};
+
+    use super::*;
+
+    async fn storage() -> PostgresStorage {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        PostgresStorage::connect(&url, 3, Duration::from_secs(1200))
+            .await
+            .unwrap()
+    }
+
+    fn unique(name: &str) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        format!("{}-{}", name, nanos)
+    }
+
+    fn observation(host: &str, mods: &[(&str, &str)]) -> ServerObservation {
+        with_players(host, mods, &[])
+    }
+
+    fn with_players(host: &str, mods: &[(&str, &str)], sample: &[&str]) -> ServerObservation {
+        let sample: Vec<_> = sample
+            .iter()
+            .map(|uuid| json!({"name": "player", "id": uuid}))
+            .collect();
+        let status = json!({
+            "version": {"name": "1.20.1", "protocol": 763},
+            "players": {"max": 20, "online": sample.len(), "sample": sample},
+            "description": "test server",
+        });
+        let mut data = ResponseData::from_value(&status);
+        data.host = host.to_owned();
+        data.port = 25565;
+        let response = Response {
+            len: 0,
+            packet_id: 0,
+            data,
+            raw: status,
+            login: None,
+            peer: None,
+        };
+
+        let mut observation = ServerObservation::from_response(&response, Duration::ZERO, false);
+        observation.mods = mods
+            .iter()
+            .map(|(mod_id, version)| ServerMod {
+                mod_id: mod_id.to_string(),
+                version: version.to_string(),
+            })
+            .collect();
+        observation
+    }
+
+    async fn installs(storage: &PostgresStorage, mod_id: &str, host: &str) -> Vec<ModInstall> {
+        let mut installs = storage.find_mods(mod_id).await.unwrap();
+        installs.retain(|install| install.host == host);
+        installs
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database in TEST_DATABASE_URL"]
+    async fn stores_duplicate_mods_once() {
+        let storage = storage().await;
+        let host = unique("duplicate-mods");
+        let mod_id = unique("jei");
+
+        let observation = observation(&host, &[(&mod_id, "15.2.0"), (&mod_id, "15.2.0")]);
+        storage.record_server(&observation).await.unwrap();
+
+        assert_eq!(installs(&storage, &mod_id, &host).await.len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database in TEST_DATABASE_URL"]
+    async fn drops_mods_no_longer_listed() {
+        let storage = storage().await;
+        let host = unique("stale-mods");
+        let (kept, dropped) = (unique("forge"), unique("spark"));
+
+        let first = observation(&host, &[(&kept, "47.2.0"), (&dropped, "1.10.53")]);
+        storage.record_server(&first).await.unwrap();
+        let mut second = observation(&host, &[(&kept, "47.2.1")]);
+        second.observed_at = DateTime::from_millis(first.observed_at.timestamp_millis() + 1000);
+        storage.record_server(&second).await.unwrap();
+
+        let kept = installs(&storage, &kept, &host).await;
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].version, "47.2.1");
+        assert!(installs(&storage, &dropped, &host).await.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database in TEST_DATABASE_URL"]
+    async fn keeps_mods_of_truncated_lists() {
+        let storage = storage().await;
+        let host = unique("truncated-mods");
+        let mod_id = unique("create");
+
+        let first = observation(&host, &[(&mod_id, "0.5.1")]);
+        storage.record_server(&first).await.unwrap();
+        let mut second = observation(&host, &[]);
+        second.mods_truncated = true;
+        second.observed_at = DateTime::from_millis(first.observed_at.timestamp_millis() + 1000);
+        storage.record_server(&second).await.unwrap();
+
+        assert_eq!(installs(&storage, &mod_id, &host).await.len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database in TEST_DATABASE_URL"]
+    async fn leaves_out_anonymous_players() {
+        let storage = storage().await;
+        let host = unique("anonymous-players");
+        let anonymous = "00000000-0000-0000-0000-000000000000";
+        let uuid = unique("player");
+
+        let observation = with_players(&host, &[], &[anonymous, &uuid]);
+        storage.record_server(&observation).await.unwrap();
+        storage.record_players(&observation).await.unwrap();
+
+        let client = storage.client().await.unwrap();
+        let linked: Vec<String> = client
+            .query(
+                "SELECT sp.uuid FROM server_players sp
+                JOIN servers s ON s.id = sp.server_id
+                WHERE s.host = $1",
+                &[&host],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(linked, vec![uuid]);
+        let stored = client
+            .query_opt("SELECT 1 FROM players WHERE uuid = $1", &[&anonymous])
+            .await
+            .unwrap();
+        assert!(stored.is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database in TEST_DATABASE_URL"]
+    async fn splits_sessions_at_long_gaps() {
+        let storage = storage().await;
+        let host = unique("session-gap");
+        let uuid = unique("player");
+
+        let first = with_players(&host, &[], &[&uuid]);
+        storage.record_server(&first).await.unwrap();
+        let mut second = with_players(&host, &[], &[&uuid]);
+        second.observed_at =
+            DateTime::from_millis(first.observed_at.timestamp_millis() + 60 * 60 * 1000);
+        storage.record_server(&second).await.unwrap();
+
+        let sessions = storage.player_sessions(&uuid).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].started_at, second.observed_at);
+        assert_eq!(sessions[0].ended_at, None);
+        assert_eq!(sessions[1].ended_at, Some(first.observed_at));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database in TEST_DATABASE_URL"]
+    async fn applies_repeated_servers_of_a_batch_in_order() {
+        let storage = storage().await;
+        let (host, other) = (unique("batch"), unique("batch-other"));
+        let uuid = unique("player");
+
+        let first = with_players(&host, &[], &[&uuid]);
+        let at = |offset: i64| DateTime::from_millis(first.observed_at.timestamp_millis() + offset);
+        let failure = ProbeFailure {
+            host: host.clone(),
+            port: 25565,
+            observed_at: at(1000),
+            error: "timeout".to_owned(),
+            message: "timed out".to_owned(),
+            status: ServerStatus::Unreachable,
+        };
+        let mut second = with_players(&host, &[], &[&uuid]);
+        second.observed_at = at(2000);
+        let batch = [
+            PendingWrite::Observation(Box::new(first.clone())),
+            PendingWrite::Failure(failure),
+            PendingWrite::Observation(Box::new(second.clone())),
+            PendingWrite::Observation(Box::new(observation(&other, &[]))),
+        ];
+        storage.record_batch(&batch).await.unwrap();
+
+        let client = storage.client().await.unwrap();
+        let row = client
+            .query_one(
+                "SELECT s.status, s.consecutive_failures, COUNT(o.*),
+                    COUNT(o.*) FILTER (WHERE NOT o.online)
+                FROM servers s JOIN observations o ON o.server_id = s.id
+                WHERE s.host = $1 GROUP BY s.id",
+                &[&host],
+            )
+            .await
+            .unwrap();
+        assert_eq!(row.get::<_, String>(0), "online");
+        assert_eq!(row.get::<_, i32>(1), 0);
+        assert_eq!((row.get::<_, i64>(2), row.get::<_, i64>(3)), (3, 1));
+
+        // The failure closed the first session, the second sighting opened another
+        let sessions = storage.player_sessions(&uuid).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].started_at, second.observed_at);
+        assert_eq!(sessions[1].ended_at, Some(first.observed_at));
+        let times_seen: i32 = client
+            .query_one(
+                "SELECT sp.times_seen FROM server_players sp
+                JOIN servers s ON s.id = sp.server_id WHERE s.host = $1",
+                &[&host],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(times_seen, 2);
+    }
+}
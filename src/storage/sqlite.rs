@@ -0,0 +1,843 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use mongodb::bson::DateTime;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::model::{
+    failure::ProbeFailure,
+    history::HistoryEntry,
+    mods::ModInstall,
+    observation::ServerObservation,
+    server::ServerStatus,
+    session::{OpenSession, PlayerSession, SessionChanges},
+    uuid::UUID,
+};
+
+use super::{
+    HistoryQuery, OnlineCount, PendingWrite, PlayerCount, PlayerFilter, PlayerRow, ScheduledProbe,
+    ServerFilter, ServerRow, ServerState, Storage, StorageError, StorageResult, Uptime,
+    VersionCount, WatchTarget,
+};
+
+/// Schema changes, applied in order. `PRAGMA user_version` records how many
+/// have been applied so far, so only ever append to this list.
+const MIGRATIONS: [&str; 8] = [
+    r#"
+CREATE TABLE servers (
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    whitelist INTEGER NOT NULL DEFAULT 0,
+    motd TEXT NOT NULL,
+    version_name TEXT NOT NULL,
+    protocol INTEGER NOT NULL,
+    max_players INTEGER NOT NULL,
+    online_players INTEGER NOT NULL,
+    online_list TEXT NOT NULL,
+    forge INTEGER NOT NULL,
+    mods_truncated INTEGER NOT NULL,
+    forge_channels TEXT NOT NULL,
+    enforces_secure_chat INTEGER NOT NULL,
+    previews_chat INTEGER,
+    prevents_chat_reports INTEGER,
+    is_modded INTEGER,
+    mod_info_type TEXT,
+    extensions TEXT NOT NULL,
+    software TEXT NOT NULL,
+    resolved_version TEXT NOT NULL,
+    parse_warnings TEXT NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_updated INTEGER NOT NULL,
+    PRIMARY KEY (host, port)
+);
+
+CREATE TABLE players (
+    uuid TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    last_seen INTEGER NOT NULL,
+    last_updated INTEGER NOT NULL
+);
+
+CREATE TABLE observations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    observed_at INTEGER NOT NULL,
+    online_players INTEGER NOT NULL,
+    max_players INTEGER NOT NULL,
+    protocol INTEGER NOT NULL,
+    version_name TEXT NOT NULL,
+    motd TEXT NOT NULL
+);
+CREATE INDEX observations_server ON observations (host, port, observed_at);
+
+CREATE TABLE historic_players (
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    uuid TEXT NOT NULL,
+    last_seen INTEGER NOT NULL,
+    PRIMARY KEY (host, port, uuid)
+);
+CREATE INDEX historic_players_uuid ON historic_players (uuid);
+
+CREATE TABLE mods (
+    mod_id TEXT NOT NULL,
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    version TEXT NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    PRIMARY KEY (mod_id, host, port)
+);
+
+CREATE TABLE raw_responses (
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    received INTEGER NOT NULL,
+    status TEXT NOT NULL
+);
+"#,
+    // History of offline probes too, with latency and the MOTD only as a hash
+    r#"
+CREATE TABLE observations_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    observed_at INTEGER NOT NULL,
+    online INTEGER NOT NULL,
+    online_players INTEGER,
+    max_players INTEGER,
+    latency_ms INTEGER,
+    protocol INTEGER,
+    version_name TEXT,
+    motd_hash TEXT
+);
+INSERT INTO observations_new (
+    id, host, port, observed_at, online, online_players, max_players, protocol, version_name
+)
+SELECT id, host, port, observed_at, 1, online_players, max_players, protocol, version_name
+FROM observations;
+DROP TABLE observations;
+ALTER TABLE observations_new RENAME TO observations;
+CREATE INDEX observations_server ON observations (host, port, observed_at);
+"#,
+    r#"
+CREATE TABLE player_sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid TEXT NOT NULL,
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    started_at INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    ended_at INTEGER
+);
+CREATE UNIQUE INDEX player_sessions_open ON player_sessions (host, port, uuid)
+    WHERE ended_at IS NULL;
+CREATE INDEX player_sessions_uuid ON player_sessions (uuid, started_at);
+"#,
+    r#"
+ALTER TABLE historic_players RENAME TO server_players;
+ALTER TABLE server_players ADD COLUMN first_seen INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE server_players ADD COLUMN times_seen INTEGER NOT NULL DEFAULT 1;
+UPDATE server_players SET first_seen = last_seen;
+DROP INDEX historic_players_uuid;
+CREATE INDEX server_players_uuid ON server_players (uuid, last_seen);
+"#,
+    r#"
+ALTER TABLE servers ADD COLUMN last_error TEXT;
+ALTER TABLE servers ADD COLUMN last_error_message TEXT;
+ALTER TABLE servers ADD COLUMN last_error_at INTEGER;
+ALTER TABLE servers ADD COLUMN consecutive_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE observations ADD COLUMN error TEXT;
+"#,
+    r#"
+ALTER TABLE servers ADD COLUMN status TEXT NOT NULL DEFAULT 'online';
+ALTER TABLE servers ADD COLUMN status_since INTEGER;
+UPDATE servers SET status = 'unreachable' WHERE consecutive_failures > 0;
+UPDATE servers SET status_since = COALESCE(last_error_at, last_updated);
+CREATE INDEX servers_status ON servers (status);
+"#,
+    r#"
+ALTER TABLE servers ADD COLUMN next_probe_at INTEGER;
+"#,
+    r#"
+CREATE INDEX players_name ON players (name COLLATE NOCASE);
+"#,
+];
+
+/// Embedded backend so the sentry can run without any external services.
+///
+/// rusqlite is blocking, every call runs on the blocking thread pool with the
+/// connection behind a mutex.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+    /// Consecutive failures after which a server is marked gone
+    gone_after: i32,
+    /// Time without a sighting after which a player's session is closed
+    session_gap: Duration,
+}
+
+impl SqliteStorage {
+    pub fn open(
+        path: impl AsRef<Path>,
+        gone_after: i32,
+        session_gap: Duration,
+    ) -> StorageResult<Self> {
+        let mut connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        migrate(&mut connection)?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            gone_after,
+            session_gap,
+        })
+    }
+
+    async fn with_connection<T, F>(&self, f: F) -> StorageResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap();
+            f(&mut connection)
+        })
+        .await
+        .expect("sqlite task panicked")
+        .map_err(StorageError::from)
+    }
+}
+
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let applied: i64 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        let tx = connection.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", version as i64 + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+fn insert_history(tx: &rusqlite::Transaction, entry: &HistoryEntry) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO observations (
+            host, port, observed_at, online, online_players, max_players, latency_ms,
+            protocol, version_name, motd_hash, error
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            entry.host,
+            entry.port,
+            entry.observed_at.timestamp_millis(),
+            entry.online,
+            entry.online_players,
+            entry.max_players,
+            entry.latency_ms,
+            entry.protocol,
+            entry.version_name,
+            entry.motd_hash,
+            entry.error,
+        ],
+    )?;
+    Ok(())
+}
+
+/// The server's open sessions, keyed by player.
+fn open_sessions(
+    tx: &rusqlite::Transaction,
+    host: &str,
+    port: i16,
+) -> rusqlite::Result<HashMap<UUID, OpenSession>> {
+    let mut statement = tx.prepare(
+        "SELECT uuid, started_at, last_seen FROM player_sessions
+        WHERE host = ?1 AND port = ?2 AND ended_at IS NULL",
+    )?;
+    let sessions = statement.query_map(params![host, port], |row| {
+        Ok((
+            UUID(row.get(0)?),
+            OpenSession {
+                started_at: DateTime::from_millis(row.get(1)?),
+                last_seen: DateTime::from_millis(row.get(2)?),
+            },
+        ))
+    })?;
+    sessions.collect()
+}
+
+fn write_server(
+    tx: &rusqlite::Transaction,
+    o: &ServerObservation,
+    session_gap: Duration,
+) -> rusqlite::Result<()> {
+    let observed_at = o.observed_at.timestamp_millis();
+
+    let changes = SessionChanges::new(&open_sessions(tx, &o.host, o.port)?, o, session_gap);
+
+    tx.execute(
+        "INSERT INTO servers (
+            host, port, motd, version_name, protocol, max_players, online_players,
+            online_list, forge, mods_truncated, forge_channels, enforces_secure_chat,
+            previews_chat, prevents_chat_reports, is_modded, mod_info_type, extensions,
+            software, resolved_version, parse_warnings, first_seen, last_updated,
+            status_since
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+            ?17, ?18, ?19, ?20, ?21, ?21, ?21)
+        ON CONFLICT (host, port) DO UPDATE SET
+            motd = excluded.motd,
+            version_name = excluded.version_name,
+            protocol = excluded.protocol,
+            max_players = excluded.max_players,
+            online_players = excluded.online_players,
+            online_list = excluded.online_list,
+            forge = excluded.forge,
+            mods_truncated = excluded.mods_truncated,
+            forge_channels = excluded.forge_channels,
+            enforces_secure_chat = excluded.enforces_secure_chat,
+            previews_chat = excluded.previews_chat,
+            prevents_chat_reports = excluded.prevents_chat_reports,
+            is_modded = excluded.is_modded,
+            mod_info_type = excluded.mod_info_type,
+            extensions = excluded.extensions,
+            software = excluded.software,
+            resolved_version = excluded.resolved_version,
+            parse_warnings = excluded.parse_warnings,
+            last_updated = excluded.last_updated,
+            consecutive_failures = 0,
+            status_since = CASE WHEN status = 'online' THEN status_since
+                ELSE excluded.status_since END,
+            status = 'online'",
+        params![
+            o.host,
+            o.port,
+            o.motd,
+            o.version.name,
+            o.version.protocol,
+            o.online.max,
+            o.online.players,
+            json(&changes.online),
+            o.forge,
+            o.mods_truncated,
+            json(&o.forge_channels),
+            o.enforces_secure_chat,
+            o.previews_chat,
+            o.prevents_chat_reports,
+            o.is_modded,
+            o.mod_info_type,
+            json(&o.extensions),
+            json(&o.software),
+            json(&o.resolved_version),
+            json(&o.parse_warnings),
+            observed_at,
+        ],
+    )?;
+
+    insert_history(tx, &HistoryEntry::online(o))?;
+
+    for uuid in &changes.ended {
+        tx.execute(
+            "UPDATE player_sessions SET ended_at = last_seen
+            WHERE host = ?1 AND port = ?2 AND uuid = ?3 AND ended_at IS NULL",
+            params![o.host, o.port, uuid.0],
+        )?;
+    }
+
+    for player in &changes.online {
+        tx.execute(
+            "INSERT INTO player_sessions (uuid, host, port, started_at, last_seen)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (host, port, uuid) WHERE ended_at IS NULL
+            DO UPDATE SET last_seen = excluded.last_seen",
+            params![
+                player.uuid.0,
+                o.host,
+                o.port,
+                player.online_since.timestamp_millis(),
+                observed_at
+            ],
+        )?;
+    }
+
+    for online_player in o.known_online() {
+        tx.execute(
+            "INSERT INTO server_players (host, port, uuid, first_seen, last_seen)
+            VALUES (?1, ?2, ?3, ?4, ?4)
+            ON CONFLICT (host, port, uuid) DO UPDATE SET
+                last_seen = excluded.last_seen,
+                times_seen = server_players.times_seen + 1",
+            params![o.host, o.port, online_player.uuid.0, observed_at],
+        )?;
+    }
+
+    for server_mod in &o.mods {
+        tx.execute(
+            "INSERT INTO mods (mod_id, host, port, version, first_seen, last_seen)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?5)
+            ON CONFLICT (mod_id, host, port) DO UPDATE SET
+                version = excluded.version,
+                last_seen = excluded.last_seen",
+            params![
+                server_mod.mod_id,
+                o.host,
+                o.port,
+                server_mod.version,
+                observed_at
+            ],
+        )?;
+    }
+    // A truncated list doesn't say which mods are gone
+    if !o.mods_truncated {
+        tx.execute(
+            "DELETE FROM mods WHERE host = ?1 AND port = ?2 AND last_seen < ?3",
+            params![o.host, o.port, observed_at],
+        )?;
+    }
+
+    if let Some(status) = &o.raw {
+        tx.execute(
+            "INSERT INTO raw_responses (host, port, received, status)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![o.host, o.port, observed_at, status],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Last error, offline history entry and closed sessions, for servers we
+/// know about.
+fn write_failure(
+    tx: &rusqlite::Transaction,
+    failure: &ProbeFailure,
+    gone_after: i32,
+) -> rusqlite::Result<()> {
+    // Every expression sees the row as it was before the update
+    let updated = tx.execute(
+        "UPDATE servers SET
+            last_error = ?3,
+            last_error_message = ?4,
+            last_error_at = ?5,
+            consecutive_failures = consecutive_failures + 1,
+            status = CASE WHEN consecutive_failures + 1 >= ?7 THEN 'gone' ELSE ?6 END,
+            status_since = CASE
+                WHEN status = CASE WHEN consecutive_failures + 1 >= ?7 THEN 'gone' ELSE ?6 END
+                THEN status_since ELSE ?5 END
+        WHERE host = ?1 AND port = ?2",
+        params![
+            failure.host,
+            failure.port,
+            failure.error,
+            failure.message,
+            failure.observed_at.timestamp_millis(),
+            failure.status.as_str(),
+            gone_after,
+        ],
+    )?;
+    if updated > 0 {
+        insert_history(tx, &HistoryEntry::offline(failure))?;
+        tx.execute(
+            "UPDATE player_sessions SET ended_at = last_seen
+            WHERE host = ?1 AND port = ?2 AND ended_at IS NULL",
+            params![failure.host, failure.port],
+        )?;
+    }
+    Ok(())
+}
+
+fn write_schedule(tx: &rusqlite::Transaction, probe: &ScheduledProbe) -> rusqlite::Result<()> {
+    tx.execute(
+        "UPDATE servers SET next_probe_at = ?3 WHERE host = ?1 AND port = ?2",
+        params![
+            probe.host,
+            probe.port,
+            probe.next_probe_at.timestamp_millis()
+        ],
+    )?;
+    Ok(())
+}
+
+fn write_players(tx: &rusqlite::Transaction, o: &ServerObservation) -> rusqlite::Result<()> {
+    let observed_at = o.observed_at.timestamp_millis();
+    let now = DateTime::now().timestamp_millis();
+
+    for player in o.known_players() {
+        tx.execute(
+            "INSERT INTO players (uuid, name, last_seen, last_updated)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (uuid) DO UPDATE SET
+                name = excluded.name,
+                last_seen = excluded.last_seen,
+                last_updated = excluded.last_updated",
+            params![player.id.0, player.name, observed_at, now],
+        )?;
+    }
+    Ok(())
+}
+
+fn json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+fn status(s: String) -> ServerStatus {
+    ServerStatus::parse(&s).unwrap_or_default()
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn record_server(&self, observation: &ServerObservation) -> StorageResult<()> {
+        let observation = observation.clone();
+        let session_gap = self.session_gap;
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+            write_server(&tx, &observation, session_gap)?;
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn record_failure(&self, failure: &ProbeFailure) -> StorageResult<()> {
+        let failure = failure.clone();
+        let gone_after = self.gone_after;
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+            write_failure(&tx, &failure, gone_after)?;
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn record_players(&self, observation: &ServerObservation) -> StorageResult<()> {
+        let observation = observation.clone();
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+            write_players(&tx, &observation)?;
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn schedule_probes(&self, probes: &[ScheduledProbe]) -> StorageResult<()> {
+        let probes = probes.to_vec();
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+            for probe in &probes {
+                write_schedule(&tx, probe)?;
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn record_batch(&self, batch: &[PendingWrite]) -> StorageResult<()> {
+        let batch = batch.to_vec();
+        let gone_after = self.gone_after;
+        let session_gap = self.session_gap;
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+            for write in &batch {
+                match write {
+                    PendingWrite::Observation(observation) => {
+                        write_server(&tx, observation, session_gap)?;
+                        write_players(&tx, observation)?;
+                    }
+                    PendingWrite::Failure(failure) => write_failure(&tx, failure, gone_after)?,
+                    PendingWrite::Schedule(probe) => write_schedule(&tx, probe)?,
+                }
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn find_mods(&self, mod_id: &str) -> StorageResult<Vec<ModInstall>> {
+        let mod_id = mod_id.to_owned();
+
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT mod_id, version, host, port, first_seen, last_seen
+                FROM mods WHERE mod_id = ?1",
+            )?;
+            let installs = statement.query_map(params![mod_id], |row| {
+                Ok(ModInstall {
+                    mod_id: row.get(0)?,
+                    version: row.get(1)?,
+                    host: row.get(2)?,
+                    port: row.get(3)?,
+                    first_seen: DateTime::from_millis(row.get(4)?),
+                    last_seen: DateTime::from_millis(row.get(5)?),
+                })
+            })?;
+            installs.collect()
+        })
+        .await
+    }
+
+    async fn version_counts(&self) -> StorageResult<Vec<VersionCount>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT protocol, COUNT(*),
+                    SUM(CASE WHEN json_extract(resolved_version, '$.mismatch') THEN 1 ELSE 0 END)
+                FROM servers GROUP BY protocol ORDER BY COUNT(*) DESC",
+            )?;
+            let counts = statement.query_map([], |row| {
+                Ok(VersionCount {
+                    protocol: row.get(0)?,
+                    servers: row.get(1)?,
+                    mismatched: row.get(2)?,
+                })
+            })?;
+            counts.collect()
+        })
+        .await
+    }
+
+    async fn online_counts(&self) -> StorageResult<Vec<OnlineCount>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT protocol, COUNT(*), COALESCE(SUM(online_players), 0)
+                FROM servers WHERE status = 'online' GROUP BY protocol",
+            )?;
+            let counts = statement.query_map([], |row| {
+                Ok(OnlineCount {
+                    protocol: row.get(0)?,
+                    servers: row.get(1)?,
+                    players: row.get(2)?,
+                })
+            })?;
+            counts.collect()
+        })
+        .await
+    }
+
+    async fn watch_targets(&self) -> StorageResult<Vec<WatchTarget>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT host, port, status, consecutive_failures, online_players, next_probe_at
+                FROM servers",
+            )?;
+            let targets = statement.query_map([], |row| {
+                Ok(WatchTarget {
+                    host: row.get(0)?,
+                    port: row.get(1)?,
+                    status: status(row.get(2)?),
+                    consecutive_failures: row.get(3)?,
+                    online_players: row.get(4)?,
+                    next_probe_at: row.get::<_, Option<i64>>(5)?.map(DateTime::from_millis),
+                })
+            })?;
+            targets.collect()
+        })
+        .await
+    }
+
+    async fn server_state(&self, host: &str, port: i16) -> StorageResult<Option<ServerState>> {
+        let host = host.to_owned();
+
+        self.with_connection(move |connection| {
+            connection
+                .query_row(
+                    "SELECT status, status_since, consecutive_failures, last_error, last_error_at
+                    FROM servers WHERE host = ?1 AND port = ?2",
+                    params![host, port],
+                    |row| {
+                        Ok(ServerState {
+                            status: status(row.get(0)?),
+                            status_since: row.get::<_, Option<i64>>(1)?.map(DateTime::from_millis),
+                            consecutive_failures: row.get(2)?,
+                            last_error: row.get(3)?,
+                            last_error_at: row.get::<_, Option<i64>>(4)?.map(DateTime::from_millis),
+                        })
+                    },
+                )
+                .optional()
+        })
+        .await
+    }
+
+    async fn player_counts(&self, query: &HistoryQuery) -> StorageResult<Vec<PlayerCount>> {
+        let query = query.clone();
+
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT observed_at, online_players, max_players FROM observations
+                WHERE host = ?1 AND port = ?2 AND online
+                    AND (?3 IS NULL OR observed_at >= ?3)
+                    AND (?4 IS NULL OR observed_at < ?4)
+                ORDER BY observed_at",
+            )?;
+            let counts = statement.query_map(
+                params![
+                    query.host,
+                    query.port,
+                    query.since.map(|d| d.timestamp_millis()),
+                    query.until.map(|d| d.timestamp_millis()),
+                ],
+                |row| {
+                    Ok(PlayerCount {
+                        observed_at: DateTime::from_millis(row.get(0)?),
+                        online_players: row.get(1)?,
+                        max_players: row.get(2)?,
+                    })
+                },
+            )?;
+            counts.collect()
+        })
+        .await
+    }
+
+    async fn uptime(&self, query: &HistoryQuery) -> StorageResult<Uptime> {
+        let query = query.clone();
+
+        self.with_connection(move |connection| {
+            connection.query_row(
+                "SELECT COUNT(*), COALESCE(SUM(online), 0) FROM observations
+                WHERE host = ?1 AND port = ?2
+                    AND (?3 IS NULL OR observed_at >= ?3)
+                    AND (?4 IS NULL OR observed_at < ?4)",
+                params![
+                    query.host,
+                    query.port,
+                    query.since.map(|d| d.timestamp_millis()),
+                    query.until.map(|d| d.timestamp_millis()),
+                ],
+                |row| {
+                    Ok(Uptime {
+                        probes: row.get(0)?,
+                        online: row.get(1)?,
+                    })
+                },
+            )
+        })
+        .await
+    }
+
+    async fn player_sessions(&self, uuid: &str) -> StorageResult<Vec<PlayerSession>> {
+        let uuid = uuid.to_owned();
+
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT uuid, host, port, started_at, last_seen, ended_at FROM player_sessions
+                WHERE uuid = ?1 ORDER BY started_at DESC",
+            )?;
+            let sessions = statement.query_map(params![uuid], |row| {
+                Ok(PlayerSession {
+                    uuid: UUID(row.get(0)?),
+                    host: row.get(1)?,
+                    port: row.get(2)?,
+                    started_at: DateTime::from_millis(row.get(3)?),
+                    last_seen: DateTime::from_millis(row.get(4)?),
+                    ended_at: row.get::<_, Option<i64>>(5)?.map(DateTime::from_millis),
+                })
+            })?;
+            sessions.collect()
+        })
+        .await
+    }
+
+    async fn export_servers(&self, filter: &ServerFilter) -> StorageResult<Vec<ServerRow>> {
+        let filter = filter.clone();
+
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT s.host, s.port, s.motd, s.version_name, s.protocol, s.online_players,
+                    s.max_players, json_extract(s.software, '$.software'), s.forge,
+                    (SELECT COALESCE(group_concat(m.mod_id || '@' || m.version, ';'), '')
+                        FROM mods m WHERE m.host = s.host AND m.port = s.port),
+                    s.last_updated, s.status
+                FROM servers s
+                WHERE (?1 IS NULL OR s.protocol = ?1)
+                    AND (?2 IS NULL OR s.last_updated >= ?2)
+                    AND (?3 IS NULL OR s.last_updated < ?3)
+                    AND (?4 IS NULL OR s.online_players >= ?4)
+                    AND (?5 IS NULL OR s.status = ?5)
+                    AND (?6 IS NULL OR s.host = ?6)
+                    AND (?7 IS NULL OR s.port = ?7)
+                    AND (?8 IS NULL OR s.online_players <= ?8)
+                    AND (?9 IS NULL OR json_extract(s.software, '$.software') = ?9 COLLATE NOCASE)
+                    AND (?10 IS NULL OR EXISTS (SELECT 1 FROM mods m
+                        WHERE m.host = s.host AND m.port = s.port AND m.mod_id = ?10))
+                    AND (?11 IS NULL OR instr(lower(s.motd), lower(?11)) > 0)
+                ORDER BY s.online_players DESC, s.host, s.port
+                LIMIT ?12 OFFSET ?13",
+            )?;
+            let rows = statement.query_map(
+                params![
+                    filter.protocol,
+                    filter.updated_after.map(|d| d.timestamp_millis()),
+                    filter.updated_before.map(|d| d.timestamp_millis()),
+                    filter.min_players,
+                    filter.status.map(|status| status.as_str()),
+                    filter.host,
+                    filter.port,
+                    filter.max_players,
+                    filter.software,
+                    filter.mod_id,
+                    filter.motd,
+                    filter.limit.unwrap_or(-1),
+                    filter.offset.unwrap_or(0),
+                ],
+                |row| {
+                    Ok(ServerRow {
+                        host: row.get(0)?,
+                        port: row.get(1)?,
+                        motd: row.get(2)?,
+                        version_name: row.get(3)?,
+                        protocol: row.get(4)?,
+                        online_players: row.get(5)?,
+                        max_players: row.get(6)?,
+                        software: row.get(7)?,
+                        forge: row.get(8)?,
+                        mods: row.get(9)?,
+                        last_updated: DateTime::from_millis(row.get(10)?),
+                        status: status(row.get(11)?),
+                    })
+                },
+            )?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn export_players(&self, filter: &PlayerFilter) -> StorageResult<Vec<PlayerRow>> {
+        let filter = filter.clone();
+
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT uuid, name, last_seen, last_updated FROM players
+                WHERE (?1 IS NULL OR last_seen >= ?1) AND (?2 IS NULL OR last_seen < ?2)
+                    AND (?3 IS NULL OR uuid = ?3)
+                    AND (?4 IS NULL OR name = ?4 COLLATE NOCASE)",
+            )?;
+            let rows = statement.query_map(
+                params![
+                    filter.seen_after.map(|d| d.timestamp_millis()),
+                    filter.seen_before.map(|d| d.timestamp_millis()),
+                    filter.uuid,
+                    filter.name,
+                ],
+                |row| {
+                    Ok(PlayerRow {
+                        uuid: row.get(0)?,
+                        name: row.get(1)?,
+                        last_seen: DateTime::from_millis(row.get(2)?),
+                        last_updated: DateTime::from_millis(row.get(3)?),
+                    })
+                },
+            )?;
+            rows.collect()
+        })
+        .await
+    }
+}
@@ -1,5 +1,5 @@
 use core::panic;
-use std::{io::Bytes, slice::Iter};
+use std::slice::Iter;
 
 use tokio::{io::AsyncReadExt, net::TcpStream};
 
@@ -37,10 +37,10 @@ impl VarInt {
         let mut position = 0;
 
         let i = loop {
-            let currentByte = stream.read_u8().await?;
-            value |= (currentByte as u32 & SEGMENT_BITS) << position;
+            let current_byte = stream.read_u8().await?;
+            value |= (current_byte as u32 & SEGMENT_BITS) << position;
 
-            if (currentByte as u32 & CONTINUE_BIT) == 0 {
+            if (current_byte as u32 & CONTINUE_BIT) == 0 {
                 break value;
             }
 
@@ -54,6 +54,23 @@ impl VarInt {
         Ok(i as i32)
     }
 
+    /// Like `parse`, but `None` on truncated or overlong input instead of
+    /// panicking, for bytes that came from an untrusted peer.
+    pub fn try_parse(bytes_iter: &mut Iter<u8>) -> Option<i32> {
+        let mut value = 0u32;
+
+        for position in (0..32).step_by(7) {
+            let current_byte = *bytes_iter.next()? as u32;
+            value |= (current_byte & SEGMENT_BITS) << position;
+
+            if (current_byte & CONTINUE_BIT) == 0 {
+                return Some(value as i32);
+            }
+        }
+
+        None
+    }
+
     pub fn parse(bytes_iter: &mut Iter<u8>) -> i32 {
         let mut value = 0;
         let mut position = 0;
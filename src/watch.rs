@@ -0,0 +1,290 @@
+//! Long running rescans of every known server, each on its own schedule.
+//!
+//! Servers sit in a queue ordered by when they're due. Busy servers come
+//! around more often, failing ones back off exponentially. The next probe
+//! time is stored with the server so a restart picks up the same queue.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use mongodb::bson::DateTime;
+use rand::Rng;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::Instant;
+use tracing::{error, info};
+
+use crate::login::probe_login;
+use crate::metrics::metrics;
+use crate::model::failure::ProbeFailure;
+use crate::model::observation::ServerObservation;
+use crate::model::server::ServerStatus;
+use crate::probe::probe;
+use crate::ratelimit::{RateLimiter, RateLimits, QUEUED_PER_PROBE};
+use crate::shutdown::shutdown_signal;
+use crate::storage::buffer::WriteBuffer;
+use crate::storage::{PendingWrite, ScheduledProbe, Storage, WatchTarget};
+
+/// How often to probe a server, depending on how it's doing.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// For an online server with nobody on it
+    pub interval: Duration,
+    pub min_interval: Duration,
+    /// Also what gone servers are probed at
+    pub max_interval: Duration,
+    /// Fraction of the interval to randomly add or take away, so servers
+    /// loaded at the same time drift apart
+    pub jitter: f64,
+    pub gone_after: i32,
+}
+
+impl Schedule {
+    pub fn next_interval(&self, target: &WatchTarget) -> Duration {
+        let interval = if target.status == ServerStatus::Gone
+            || target.consecutive_failures >= self.gone_after
+        {
+            self.max_interval
+        } else if target.consecutive_failures > 0 {
+            let backoff = 1u32 << target.consecutive_failures.min(16);
+            self.interval.saturating_mul(backoff)
+        } else {
+            // Halves at 1 player, thirds at 3, quarters at 7 and so on
+            let activity = 1.0 + (target.online_players.max(0) as f64 + 1.0).log2();
+            self.interval.div_f64(activity)
+        };
+
+        jitter(
+            interval.max(self.min_interval).min(self.max_interval),
+            self.jitter,
+        )
+    }
+}
+
+fn jitter(interval: Duration, fraction: f64) -> Duration {
+    if fraction <= 0.0 {
+        return interval;
+    }
+    interval.mul_f64(1.0 + rand::thread_rng().gen_range(-fraction..=fraction))
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    pub schedule: Schedule,
+    /// Most probes running at once
+    pub concurrency: usize,
+    /// Per phase probe timeout
+    pub timeout: Duration,
+    /// How often to look for servers added since the last load
+    pub reload_interval: Duration,
+    pub store_raw: bool,
+    /// Also attempt a login with servers that answer
+    pub login_probe: bool,
+    pub rate_limits: RateLimits,
+}
+
+type ServerKey = (String, i16);
+
+/// What a finished probe means for scheduling. `Ok` with the player count,
+/// `Err` with the status the failure points to.
+struct ProbeOutcome {
+    key: ServerKey,
+    result: Result<i32, ServerStatus>,
+}
+
+/// Runs until Ctrl-C or SIGTERM, then waits for the probes in flight and
+/// returns.
+pub async fn watch(storage: Arc<dyn Storage>, buffer: Arc<WriteBuffer>, options: WatchOptions) {
+    let concurrency = options.concurrency.max(1);
+    // Targets in flight include those still waiting on the limiter, only
+    // running probes are held to `concurrency`
+    let max_in_flight = concurrency * QUEUED_PER_PROBE;
+    let probes = Arc::new(Semaphore::new(concurrency));
+    let mut targets = HashMap::<ServerKey, WatchTarget>::new();
+    let mut queue = BinaryHeap::<Reverse<(i64, ServerKey)>>::new();
+    let (results_tx, mut results) = mpsc::channel::<ProbeOutcome>(concurrency);
+    let mut in_flight = 0usize;
+    let limiter = Arc::new(RateLimiter::new(options.rate_limits));
+    info!(limit = %options.rate_limits, "rate limit");
+
+    let mut reload = tokio::time::interval(options.reload_interval);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut stopping = false;
+
+    loop {
+        if stopping && in_flight == 0 {
+            break;
+        }
+
+        metrics().probe_queue.set(queue.len() as i64);
+        let can_dispatch = !stopping && in_flight < max_in_flight && !queue.is_empty();
+        let delay = queue
+            .peek()
+            .map(|Reverse((due, _))| {
+                Duration::from_millis((due - DateTime::now().timestamp_millis()).max(0) as u64)
+            })
+            .unwrap_or_default();
+
+        tokio::select! {
+            _ = reload.tick(), if !stopping => {
+                match storage.watch_targets().await {
+                    Ok(loaded) => {
+                        let added = load(&mut targets, &mut queue, loaded, &options.schedule);
+                        if added > 0 {
+                            info!(servers = targets.len(), added, "watching servers");
+                        }
+                    }
+                    Err(err) => error!(error = %err, "error loading servers to watch"),
+                }
+            }
+            Some(outcome) = results.recv() => {
+                in_flight -= 1;
+                if let Some(target) = targets.get_mut(&outcome.key) {
+                    let next_probe_at = reschedule(target, outcome.result, &options.schedule);
+                    buffer
+                        .push(PendingWrite::Schedule(ScheduledProbe {
+                            host: target.host.clone(),
+                            port: target.port,
+                            next_probe_at,
+                        }))
+                        .await;
+                    queue.push(Reverse((next_probe_at.timestamp_millis(), outcome.key)));
+                }
+            }
+            _ = tokio::time::sleep_until(Instant::now() + delay), if can_dispatch => {
+                let now = DateTime::now().timestamp_millis();
+                while in_flight < max_in_flight {
+                    match queue.peek() {
+                        Some(Reverse((due, _))) if *due <= now => {}
+                        _ => break,
+                    }
+                    let Some(Reverse((_, key))) = queue.pop() else {
+                        break;
+                    };
+
+                    in_flight += 1;
+                    tokio::spawn(probe_target(
+                        key,
+                        buffer.clone(),
+                        limiter.clone(),
+                        probes.clone(),
+                        results_tx.clone(),
+                        options.timeout,
+                        options.store_raw,
+                        options.login_probe,
+                    ));
+                }
+            }
+            _ = &mut shutdown, if !stopping => {
+                info!(in_flight, "stopping, waiting for probes in flight");
+                stopping = true;
+            }
+        }
+    }
+}
+
+/// Adds the servers that aren't watched yet and returns how many there were.
+/// Ones that were never scheduled are spread over their first interval.
+fn load(
+    targets: &mut HashMap<ServerKey, WatchTarget>,
+    queue: &mut BinaryHeap<Reverse<(i64, ServerKey)>>,
+    loaded: Vec<WatchTarget>,
+    schedule: &Schedule,
+) -> usize {
+    let now = DateTime::now().timestamp_millis();
+    let mut added = 0;
+
+    for target in loaded {
+        let key = (target.host.clone(), target.port);
+        if targets.contains_key(&key) {
+            continue;
+        }
+
+        let due = match target.next_probe_at {
+            Some(next_probe_at) => next_probe_at.timestamp_millis(),
+            None => {
+                let interval = schedule.next_interval(&target).as_millis() as i64;
+                now + rand::thread_rng().gen_range(0..interval.max(1))
+            }
+        };
+        queue.push(Reverse((due, key.clone())));
+        targets.insert(key, target);
+        added += 1;
+    }
+
+    added
+}
+
+/// Applies a probe's outcome to the target and returns when to probe it next.
+fn reschedule(
+    target: &mut WatchTarget,
+    result: Result<i32, ServerStatus>,
+    schedule: &Schedule,
+) -> DateTime {
+    match result {
+        Ok(players) => {
+            target.status = ServerStatus::Online;
+            target.consecutive_failures = 0;
+            target.online_players = players;
+        }
+        Err(status) => {
+            target.consecutive_failures += 1;
+            target.status = if target.consecutive_failures >= schedule.gone_after {
+                ServerStatus::Gone
+            } else {
+                status
+            };
+        }
+    }
+
+    let interval = schedule.next_interval(target);
+    DateTime::from_millis(DateTime::now().timestamp_millis() + interval.as_millis() as i64)
+}
+
+/// Waits for the rate limiter and then a free probe, probes the target and
+/// stores the result.
+#[allow(clippy::too_many_arguments)]
+async fn probe_target(
+    key: ServerKey,
+    buffer: Arc<WriteBuffer>,
+    limiter: Arc<RateLimiter>,
+    probes: Arc<Semaphore>,
+    results: mpsc::Sender<ProbeOutcome>,
+    timeout: Duration,
+    store_raw: bool,
+    login_probe: bool,
+) {
+    let (host, port) = (&key.0, key.1);
+    limiter.acquire(host).await;
+    let permit = probes.acquire_owned().await.unwrap();
+    let started_at = DateTime::now();
+    let start = Instant::now();
+
+    let result = match probe(host, port, timeout).await {
+        Ok(mut response) => {
+            let latency = start.elapsed();
+            if login_probe {
+                probe_login(&mut response, timeout).await;
+            }
+            let observation = ServerObservation::from_response(&response, latency, store_raw);
+            let players = observation.online.players;
+            buffer
+                .push(PendingWrite::Observation(Box::new(observation)))
+                .await;
+            Ok(players)
+        }
+        Err(err) => {
+            let failure = ProbeFailure::new(host, port, started_at, &err);
+            let status = failure.status;
+            buffer.push(PendingWrite::Failure(failure)).await;
+            Err(status)
+        }
+    };
+
+    // Released before reporting back, the buffer is finished once the last
+    // probe has reported
+    drop((buffer, permit));
+    let _ = results.send(ProbeOutcome { key, result }).await;
+}